@@ -0,0 +1,1534 @@
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::env;
+use crate::path;
+use crate::persist::DEFAULT_TIMEOUT;
+use crate::secure;
+
+/// Where the rules are read from, this is never relative to the callers working directory.
+pub const CONFIG_PATH: &str = "/etc/execas.conf";
+
+/// What a matching rule does with the request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Permit,
+    Deny,
+}
+
+/// A single entry of a `setenv { }` block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EnvEntry {
+    /// `VAR=value` sets the variable.
+    Set(String, String),
+    /// `-VAR` removes the variable.
+    Remove(String),
+    /// `VAR` keeps the callers value.
+    Keep(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Options {
+    pub nopass: bool,
+    /// How long a successful authentication is remembered for.
+    pub persist: Option<Duration>,
+    pub keepenv: bool,
+    pub setenv: Vec<EnvEntry>,
+    /// Do not log when the rule lets a command run.
+    pub nolog: bool,
+    /// Do not log when the rule refuses a command or authentication fails.
+    pub nolog_failure: bool,
+}
+
+/// Who a rule is about, a user name or `:group`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Identity {
+    User(String),
+    Group(String),
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Identity::User(name) => write!(f, "{}", quote(name)),
+            Identity::Group(name) => write!(f, ":{}", quote(name)),
+        }
+    }
+}
+
+/// The user asking to run a command along with the names of all the groups they are in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Caller {
+    pub name: String,
+    pub groups: Vec<String>,
+}
+
+/// A caller asking to run a command as another user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub caller: Caller,
+    pub target: String,
+    /// The command and its arguments, the first element resolved to an absolute path.
+    pub command: Vec<OsString>,
+    /// `-e`, `command` is the absolute paths of the files to edit instead.
+    pub edit: bool,
+    /// The names of the machine the command would run on.
+    pub hosts: Vec<String>,
+}
+
+/// What the config says about a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision<'a> {
+    Permit(&'a Rule),
+    /// Refused by a deny rule, or by no rule matching at all.
+    Deny(Option<&'a Rule>),
+}
+
+/// `permit|deny [options] identity [as target] [on host] [cmd command [args ...] | edit path ...]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub action: Action,
+    pub options: Options,
+    pub identity: Identity,
+    pub target: Option<String>,
+    /// A host name or glob, the rule only applies on machines going by a matching name.
+    pub host: Option<String>,
+    pub cmd: Option<String>,
+    /// `None` allows any arguments, `Some(vec![])` allows none.
+    pub args: Option<Vec<String>>,
+    /// The files the rule lets the caller edit with `-e`.
+    pub edit: Option<Vec<String>>,
+    pub line: usize,
+    /// The included file the rule is from, `None` for the main config.
+    pub file: Option<PathBuf>,
+}
+
+impl Rule {
+    /// Where the rule is written, for the log.
+    pub fn location(&self) -> String {
+        match &self.file {
+            Some(file) => format!("line {} of {}", self.line, file.display()),
+            None => format!("line {}", self.line),
+        }
+    }
+
+    /// Whether the rule is about `caller` on the machine named `hosts` at all, regardless of
+    /// target or command.
+    pub fn applies_to(&self, caller: &Caller, hosts: &[String]) -> bool {
+        let identity = match &self.identity {
+            Identity::User(name) => *name == caller.name,
+            Identity::Group(name) => caller.groups.contains(name),
+        };
+
+        identity
+            && self.host.as_ref().is_none_or(|pattern| {
+                hosts
+                    .iter()
+                    .any(|host| glob(&pattern.to_ascii_lowercase(), &host.to_ascii_lowercase()))
+            })
+    }
+
+    /// Whether the rule matches `request`, bare command names are looked up in `search_path` with
+    /// `resolve`.
+    fn matches<R>(&self, request: &Request, search_path: &str, resolve: &R) -> bool
+    where
+        R: Fn(&OsStr, &OsStr) -> io::Result<PathBuf>,
+    {
+        if !self.applies_to(&request.caller, &request.hosts) {
+            return false;
+        }
+
+        if let Some(rule_target) = &self.target {
+            if *rule_target != request.target {
+                return false;
+            }
+        }
+
+        // edit rules are only about editing and command rules only about running commands, a rule
+        // with neither allows both
+        if request.edit {
+            if self.cmd.is_some() {
+                return false;
+            }
+            if let Some(edit) = &self.edit {
+                return request
+                    .command
+                    .iter()
+                    .all(|file| edit.iter().any(|allowed| same_file(file, allowed)));
+            }
+            return true;
+        }
+        if self.edit.is_some() {
+            return false;
+        }
+
+        let command = &request.command;
+        if let Some(cmd) = &self.cmd {
+            let given = match command.first() {
+                Some(given) => Path::new(given),
+                None => return false,
+            };
+
+            // a path has to be the exact file, a bare name has to be the file it resolves to in the
+            // secure path so a program of the same name anywhere else never matches
+            let matched = if cmd.contains('/') {
+                same_file(given.as_os_str(), cmd)
+            } else {
+                resolve(OsStr::new(cmd), OsStr::new(search_path)).is_ok_and(|resolved| {
+                    given == resolved || path::normalize(given) == path::normalize(&resolved)
+                })
+            };
+            if !matched {
+                return false;
+            }
+
+            if let Some(args) = &self.args {
+                let given = &command[1..];
+                if given.len() != args.len()
+                    || !given.iter().zip(args).all(|(g, a)| g == a.as_str())
+                {
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+}
+
+/// Whether `name` matches `pattern`, where `*` matches any run of characters and `?` any one.
+fn glob(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) =
+        (pattern.chars().collect(), name.chars().collect());
+    let (mut p, mut n) = (0, 0);
+    // where the last star was and how much of the name it has taken, to backtrack to
+    let mut star = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Whether the resolved `given` path is the file a rule names with `path`.
+fn same_file(given: &OsStr, path: &str) -> bool {
+    let given = Path::new(given);
+    given == Path::new(path) || given == path::normalize(Path::new(path))
+}
+
+/// Writes the rule back out in the config syntax.
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.action {
+            Action::Permit => write!(f, "permit")?,
+            Action::Deny => write!(f, "deny")?,
+        }
+
+        let options = &self.options;
+        if options.nopass {
+            write!(f, " nopass")?;
+        }
+        match options.persist {
+            Some(DEFAULT_TIMEOUT) => write!(f, " persist")?,
+            Some(timeout) => write!(f, " persist={}", timeout.as_secs())?,
+            None => {}
+        }
+        if options.keepenv {
+            write!(f, " keepenv")?;
+        }
+        if options.nolog {
+            write!(f, " nolog")?;
+        }
+        if options.nolog_failure {
+            write!(f, " nolog_failure")?;
+        }
+        if !options.setenv.is_empty() {
+            write!(f, " setenv {{")?;
+            for entry in &options.setenv {
+                match entry {
+                    EnvEntry::Set(name, value) => {
+                        write!(f, " {}", quote(&format!("{}={}", name, value)))?
+                    }
+                    EnvEntry::Remove(name) => write!(f, " {}", quote(&format!("-{}", name)))?,
+                    EnvEntry::Keep(name) => write!(f, " {}", quote(name))?,
+                }
+            }
+            write!(f, " }}")?;
+        }
+
+        write!(f, " {}", self.identity)?;
+        if let Some(target) = &self.target {
+            write!(f, " as {}", quote(target))?;
+        }
+        if let Some(host) = &self.host {
+            write!(f, " on {}", quote(host))?;
+        }
+        if let Some(cmd) = &self.cmd {
+            write!(f, " cmd {}", quote(cmd))?;
+        }
+        if let Some(args) = &self.args {
+            write!(f, " args")?;
+            for arg in args {
+                write!(f, " {}", quote(arg))?;
+            }
+        }
+        if let Some(edit) = &self.edit {
+            write!(f, " edit")?;
+            for file in edit {
+                write!(f, " {}", quote(file))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Quote a word if it would not read back as a single word.
+fn quote(word: &str) -> String {
+    let plain = !word.is_empty()
+        && !word
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, '"' | '\\' | '#' | '{' | '}'));
+    if plain {
+        return word.to_string();
+    }
+
+    let mut quoted = String::from("\"");
+    for c in word.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Global settings from `set name value` lines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Settings {
+    /// Consecutive failed authentications before a user is locked out.
+    pub max_failures: u32,
+    /// How long a locked out user has to wait.
+    pub lockout: Duration,
+    /// Where commands given without a path are looked up, never the callers `PATH`. It is also
+    /// the `PATH` the command gets unless the rule keeps the callers environment.
+    pub secure_path: String,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            max_failures: 5,
+            lockout: Duration::from_secs(15 * 60),
+            secure_path: env::SAFE_PATH.to_string(),
+        }
+    }
+}
+
+impl Settings {
+    fn apply(&mut self, setting: Setting) {
+        match setting {
+            Setting::MaxFailures(max) => self.max_failures = max,
+            Setting::Lockout(lockout) => self.lockout = lockout,
+            Setting::SecurePath(path) => self.secure_path = path,
+        }
+    }
+}
+
+/// A single `set name value` line.
+#[derive(Debug)]
+enum Setting {
+    MaxFailures(u32),
+    Lockout(Duration),
+    SecurePath(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    pub rules: Vec<Rule>,
+    pub settings: Settings,
+}
+
+impl Config {
+    /// Read and parse the config at `path` and the files it includes, refusing any of them unless
+    /// only root could have written it.
+    pub fn load(path: &Path) -> Result<Config, LoadError> {
+        Loader::new(read_trusted, list_trusted).load(path)
+    }
+
+    /// Like [`Config::load`] but without checking who could have written the files, for checking
+    /// a config before it is installed.
+    pub fn load_unchecked(path: &Path) -> Result<Config, LoadError> {
+        Loader::new(read, list).load(path)
+    }
+
+    /// Parse a config on its own, includes are refused since there is no file to be relative to.
+    pub fn parse(text: &str) -> Result<Config, ParseError> {
+        let mut config = Config::default();
+        for entry in parse_entries(text)? {
+            match entry {
+                Entry::Rule(rule) => config.rules.push(rule),
+                Entry::Setting(setting) => config.settings.apply(setting),
+                Entry::Include(include) => {
+                    return Err(error(&include.token, "includes can only be used in a file"))
+                }
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// The rules applying to `caller` in the order they appear.
+    pub fn rules_for<'a>(
+        &'a self,
+        caller: &'a Caller,
+        hosts: &'a [String],
+    ) -> impl Iterator<Item = &'a Rule> {
+        self.rules
+            .iter()
+            .filter(move |rule| rule.applies_to(caller, hosts))
+    }
+
+    /// Decide whether the request is allowed, the last matching rule wins.
+    ///
+    /// Bare command names in rules are found with `resolve`, the same way the command itself was.
+    pub fn evaluate<R>(&self, request: &Request, resolve: R) -> Decision<'_>
+    where
+        R: Fn(&OsStr, &OsStr) -> io::Result<PathBuf>,
+    {
+        let search_path = &self.settings.secure_path;
+        match self
+            .rules
+            .iter()
+            .rev()
+            .find(|rule| rule.matches(request, search_path, &resolve))
+        {
+            Some(rule) if rule.action == Action::Permit => Decision::Permit(rule),
+            rule => Decision::Deny(rule),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+/// Reads a config and every file it includes, in the order they are included.
+struct Loader {
+    read: fn(&Path) -> io::Result<String>,
+    list: fn(&Path) -> io::Result<Vec<PathBuf>>,
+    /// The files currently being loaded, including one of them again would never end.
+    loading: Vec<PathBuf>,
+    config: Config,
+}
+
+impl Loader {
+    fn new(
+        read: fn(&Path) -> io::Result<String>,
+        list: fn(&Path) -> io::Result<Vec<PathBuf>>,
+    ) -> Loader {
+        Loader {
+            read,
+            list,
+            loading: Vec::new(),
+            config: Config::default(),
+        }
+    }
+
+    fn load(mut self, path: &Path) -> Result<Config, LoadError> {
+        let text = (self.read)(path).map_err(LoadError::Io)?;
+        self.add(path, &text)?;
+        Ok(self.config)
+    }
+
+    fn add(&mut self, path: &Path, text: &str) -> Result<(), LoadError> {
+        let entries =
+            parse_entries(text).map_err(|err| LoadError::Parse(path.to_path_buf(), err))?;
+        let included = !self.loading.is_empty();
+
+        self.loading.push(path::normalize(path));
+        for entry in entries {
+            match entry {
+                Entry::Rule(mut rule) => {
+                    if included {
+                        rule.file = Some(path.to_path_buf());
+                    }
+                    self.config.rules.push(rule);
+                }
+                Entry::Setting(setting) => self.config.settings.apply(setting),
+                Entry::Include(include) => self.include(path, &include)?,
+            }
+        }
+        self.loading.pop();
+
+        Ok(())
+    }
+
+    fn include(&mut self, from: &Path, include: &Include) -> Result<(), LoadError> {
+        // problems reading an included file are reported where it is included
+        let at = |err: io::Error| {
+            LoadError::Io(io::Error::new(
+                err.kind(),
+                format!("{}:{}: {}", from.display(), include.token.line, err),
+            ))
+        };
+
+        let files = if include.dir {
+            let mut files = (self.list)(&include.path).map_err(at)?;
+            files.retain(|file| {
+                file.file_name()
+                    .and_then(OsStr::to_str)
+                    .is_some_and(is_config_name)
+            });
+            files.sort();
+            files
+        } else {
+            vec![include.path.clone()]
+        };
+
+        for file in files {
+            if self.loading.contains(&path::normalize(&file)) {
+                return Err(LoadError::Parse(
+                    from.to_path_buf(),
+                    ParseError {
+                        line: include.token.line,
+                        column: include.token.column,
+                        message: format!("{} includes itself", file.display()),
+                    },
+                ));
+            }
+
+            let text = (self.read)(&file).map_err(at)?;
+            self.add(&file, &text)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether a file in an included directory is part of the config, hidden files and editor backups
+/// are skipped.
+fn is_config_name(name: &str) -> bool {
+    !name.starts_with('.') && !name.ends_with('~')
+}
+
+fn read_trusted(path: &Path) -> io::Result<String> {
+    let mut text = String::new();
+    secure::open_trusted(path)?.read_to_string(&mut text)?;
+    Ok(text)
+}
+
+fn list_trusted(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    secure::check_trusted_dir(dir)?;
+    list(dir)
+}
+
+fn read(path: &Path) -> io::Result<String> {
+    fs::read_to_string(path)
+        .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path.display(), err)))
+}
+
+/// The regular files in `dir`, subdirectories, symlinks and the like are never part of the config.
+fn list(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    fs::read_dir(dir)
+        .and_then(|entries| {
+            let mut files = Vec::new();
+            for entry in entries {
+                let entry = entry?;
+                if entry.file_type()?.is_file() {
+                    files.push(entry.path());
+                }
+            }
+            Ok(files)
+        })
+        .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", dir.display(), err)))
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Parse(PathBuf, ParseError),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(err) => write!(f, "{}", err),
+            LoadError::Parse(path, err) => write!(f, "{}:{}", path.display(), err),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TokenKind {
+    Word(String),
+    OpenBrace,
+    CloseBrace,
+    Newline,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    line: usize,
+    column: usize,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    let (mut line, mut column) = (1, 1);
+
+    // the current word along with where it started
+    let mut word: Option<(String, usize, usize)> = None;
+    let mut quote_start: Option<(usize, usize)> = None;
+
+    macro_rules! advance {
+        ($c:expr) => {
+            if $c == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+        };
+    }
+
+    while let Some(c) = chars.next() {
+        let (start_line, start_column) = (line, column);
+        advance!(c);
+
+        if quote_start.is_some() {
+            match c {
+                '"' => quote_start = None,
+                '\\' => match chars.next() {
+                    Some(escaped) => {
+                        advance!(escaped);
+                        word.as_mut().expect("quotes start a word").0.push(escaped);
+                    }
+                    None => break,
+                },
+                _ => word.as_mut().expect("quotes start a word").0.push(c),
+            }
+            continue;
+        }
+
+        match c {
+            ' ' | '\t' | '\n' | '{' | '}' | '#' => {
+                if let Some((text, line, column)) = word.take() {
+                    tokens.push(Token {
+                        kind: TokenKind::Word(text),
+                        line,
+                        column,
+                    });
+                }
+
+                let kind = match c {
+                    '\n' => Some(TokenKind::Newline),
+                    '{' => Some(TokenKind::OpenBrace),
+                    '}' => Some(TokenKind::CloseBrace),
+                    '#' => {
+                        // comments run to the end of the line, the newline itself still ends the rule
+                        while let Some(&next) = chars.peek() {
+                            if next == '\n' {
+                                break;
+                            }
+                            advance!(next);
+                            chars.next();
+                        }
+                        None
+                    }
+                    _ => None,
+                };
+
+                if let Some(kind) = kind {
+                    tokens.push(Token {
+                        kind,
+                        line: start_line,
+                        column: start_column,
+                    });
+                }
+            }
+            '\\' => match chars.next() {
+                // a backslash at the end of a line continues the rule on the next one
+                Some('\n') => {
+                    advance!('\n');
+                    if let Some((text, line, column)) = word.take() {
+                        tokens.push(Token {
+                            kind: TokenKind::Word(text),
+                            line,
+                            column,
+                        });
+                    }
+                }
+                Some(escaped) => {
+                    advance!(escaped);
+                    word.get_or_insert_with(|| (String::new(), start_line, start_column))
+                        .0
+                        .push(escaped);
+                }
+                None => {
+                    return Err(ParseError {
+                        line: start_line,
+                        column: start_column,
+                        message: "unexpected end of file after backslash".to_string(),
+                    })
+                }
+            },
+            '"' => {
+                word.get_or_insert_with(|| (String::new(), start_line, start_column));
+                quote_start = Some((start_line, start_column));
+            }
+            _ => word
+                .get_or_insert_with(|| (String::new(), start_line, start_column))
+                .0
+                .push(c),
+        }
+    }
+
+    if let Some((line, column)) = quote_start {
+        return Err(ParseError {
+            line,
+            column,
+            message: "unterminated quote".to_string(),
+        });
+    }
+
+    if let Some((text, line, column)) = word.take() {
+        tokens.push(Token {
+            kind: TokenKind::Word(text),
+            line,
+            column,
+        });
+    }
+
+    tokens.push(Token {
+        kind: TokenKind::Newline,
+        line,
+        column,
+    });
+
+    Ok(tokens)
+}
+
+/// A line of the config, includes are loaded by whoever parsed it.
+enum Entry {
+    Rule(Rule),
+    Setting(Setting),
+    Include(Include),
+}
+
+/// `include file` or `includedir dir`.
+struct Include {
+    path: PathBuf,
+    dir: bool,
+    /// The include keyword, for errors about it.
+    token: Token,
+}
+
+fn parse_entries(text: &str) -> Result<Vec<Entry>, ParseError> {
+    let tokens = tokenize(text)?;
+    Parser { tokens, pos: 0 }.parse()
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn parse(mut self) -> Result<Vec<Entry>, ParseError> {
+        let mut entries = Vec::new();
+
+        while self.pos < self.tokens.len() {
+            if self.peek().kind == TokenKind::Newline {
+                self.pos += 1;
+                continue;
+            }
+
+            if self.peek_word("set") {
+                self.pos += 1;
+                entries.push(Entry::Setting(self.setting()?));
+                continue;
+            }
+
+            if self.peek_word("include") || self.peek_word("includedir") {
+                entries.push(Entry::Include(self.include()?));
+                continue;
+            }
+
+            entries.push(Entry::Rule(self.rule()?));
+        }
+
+        Ok(entries)
+    }
+
+    fn include(&mut self) -> Result<Include, ParseError> {
+        let token = self.next();
+        let dir = matches!(&token.kind, TokenKind::Word(word) if word == "includedir");
+
+        let path_token = self.peek().clone();
+        let path = self.word("expected a path to include")?;
+        if !path.starts_with('/') {
+            return Err(error(&path_token, "expected an absolute path to include"));
+        }
+
+        let end = self.next();
+        if end.kind != TokenKind::Newline {
+            return Err(error(&end, "expected end of line"));
+        }
+
+        Ok(Include {
+            path: PathBuf::from(path),
+            dir,
+            token,
+        })
+    }
+
+    fn setting(&mut self) -> Result<Setting, ParseError> {
+        let name_token = self.peek().clone();
+        let name = self.word("expected a setting name")?;
+        let value_token = self.peek().clone();
+        let value = self.word("expected a value for the setting")?;
+
+        let number = || -> Result<u64, ParseError> {
+            value
+                .parse()
+                .map_err(|_| error(&value_token, "expected a number"))
+        };
+        let setting = match name.as_str() {
+            "max_failures" => match number()?.try_into() {
+                Ok(0) | Err(_) => {
+                    return Err(error(
+                        &value_token,
+                        "expected a number from 1 to 4294967295",
+                    ))
+                }
+                Ok(max) => Setting::MaxFailures(max),
+            },
+            "lockout" => Setting::Lockout(Duration::from_secs(number()?)),
+            "secure_path" => {
+                // a relative or empty entry would search the working directory
+                if value.split(':').any(|dir| !dir.starts_with('/')) {
+                    return Err(error(
+                        &value_token,
+                        "expected absolute directories separated by colons",
+                    ));
+                }
+                Setting::SecurePath(value)
+            }
+            _ => return Err(error(&name_token, "unknown setting")),
+        };
+
+        let end = self.next();
+        if end.kind != TokenKind::Newline {
+            return Err(error(&end, "expected end of line"));
+        }
+
+        Ok(setting)
+    }
+
+    fn rule(&mut self) -> Result<Rule, ParseError> {
+        let start = self.next();
+        let action = match &start.kind {
+            TokenKind::Word(word) if word == "permit" => Action::Permit,
+            TokenKind::Word(word) if word == "deny" => Action::Deny,
+            _ => return Err(error(&start, "expected permit or deny")),
+        };
+
+        let options = self.options()?;
+
+        let identity = self.identity()?;
+
+        let mut target = None;
+        if self.peek_word("as") {
+            self.pos += 1;
+            target = Some(self.word("expected a target user after as")?);
+        }
+
+        let mut host = None;
+        if self.peek_word("on") {
+            self.pos += 1;
+            host = Some(self.word("expected a host name after on")?);
+        }
+
+        let mut cmd = None;
+        let mut args = None;
+        if self.peek_word("cmd") {
+            self.pos += 1;
+            let token = self.peek().clone();
+            let word = self.word("expected a command after cmd")?;
+            if word.contains('/') && !word.starts_with('/') {
+                return Err(error(
+                    &token,
+                    "expected an absolute path or a command name after cmd",
+                ));
+            }
+            cmd = Some(word);
+
+            if self.peek_word("args") {
+                self.pos += 1;
+                let mut list = Vec::new();
+                while let TokenKind::Word(arg) = &self.peek().kind {
+                    list.push(arg.clone());
+                    self.pos += 1;
+                }
+                args = Some(list);
+            }
+        }
+
+        let mut edit = None;
+        if cmd.is_none() && self.peek_word("edit") {
+            self.pos += 1;
+            let mut files = Vec::new();
+            while let TokenKind::Word(file) = &self.peek().kind {
+                if !file.starts_with('/') {
+                    return Err(error(self.peek(), "expected an absolute path to edit"));
+                }
+                files.push(file.clone());
+                self.pos += 1;
+            }
+            if files.is_empty() {
+                return Err(error(self.peek(), "expected a file to edit after edit"));
+            }
+            edit = Some(files);
+        }
+
+        let end = self.next();
+        if end.kind != TokenKind::Newline {
+            return Err(error(&end, "expected end of line"));
+        }
+
+        Ok(Rule {
+            action,
+            options,
+            identity,
+            target,
+            host,
+            cmd,
+            args,
+            edit,
+            line: start.line,
+            file: None,
+        })
+    }
+
+    fn options(&mut self) -> Result<Options, ParseError> {
+        let mut options = Options::default();
+
+        loop {
+            let token = self.peek().clone();
+            match &token.kind {
+                TokenKind::Word(word) if word == "nopass" => options.nopass = true,
+                TokenKind::Word(word) if word == "persist" => {
+                    options.persist = Some(DEFAULT_TIMEOUT)
+                }
+                TokenKind::Word(word) if word.starts_with("persist=") => {
+                    let seconds = word["persist=".len()..]
+                        .parse()
+                        .map_err(|_| error(&token, "expected a number of seconds for persist"))?;
+                    options.persist = Some(Duration::from_secs(seconds));
+                }
+                TokenKind::Word(word) if word == "keepenv" => options.keepenv = true,
+                TokenKind::Word(word) if word == "nolog" => options.nolog = true,
+                TokenKind::Word(word) if word == "nolog_failure" => options.nolog_failure = true,
+                TokenKind::Word(word) if word == "setenv" => {
+                    self.pos += 1;
+                    let open = self.next();
+                    if open.kind != TokenKind::OpenBrace {
+                        return Err(error(&open, "expected { after setenv"));
+                    }
+                    options.setenv.extend(self.setenv()?);
+                    continue;
+                }
+                _ => return Ok(options),
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn setenv(&mut self) -> Result<Vec<EnvEntry>, ParseError> {
+        let mut entries = Vec::new();
+
+        loop {
+            let token = self.next();
+            let word = match &token.kind {
+                TokenKind::CloseBrace => return Ok(entries),
+                // a setenv block may span lines
+                TokenKind::Newline if self.pos < self.tokens.len() => continue,
+                TokenKind::Newline => return Err(error(&token, "unterminated setenv block")),
+                TokenKind::OpenBrace => return Err(error(&token, "unexpected {")),
+                TokenKind::Word(word) => word,
+            };
+
+            let entry = if let Some(name) = word.strip_prefix('-') {
+                EnvEntry::Remove(name.to_string())
+            } else if let Some((name, value)) = word.split_once('=') {
+                EnvEntry::Set(name.to_string(), value.to_string())
+            } else {
+                EnvEntry::Keep(word.clone())
+            };
+
+            let name = match &entry {
+                EnvEntry::Set(name, _) | EnvEntry::Remove(name) | EnvEntry::Keep(name) => name,
+            };
+            if name.is_empty() {
+                return Err(error(&token, "expected an environment variable name"));
+            }
+
+            entries.push(entry);
+        }
+    }
+
+    fn identity(&mut self) -> Result<Identity, ParseError> {
+        let token = self.peek().clone();
+        let word = self.word("expected a user name or :group")?;
+
+        match word.strip_prefix(':') {
+            Some("") => Err(error(&token, "expected a group name after :")),
+            Some(group) => Ok(Identity::Group(group.to_string())),
+            None => Ok(Identity::User(word)),
+        }
+    }
+
+    fn word(&mut self, message: &str) -> Result<String, ParseError> {
+        let token = self.next();
+        match token.kind {
+            TokenKind::Word(word) => Ok(word),
+            _ => Err(error(&token, message)),
+        }
+    }
+
+    fn peek_word(&self, keyword: &str) -> bool {
+        matches!(&self.peek().kind, TokenKind::Word(word) if word == keyword)
+    }
+
+    fn peek(&self) -> &Token {
+        // the token list always ends in a newline which is never consumed past
+        &self.tokens[self.pos.min(self.tokens.len() - 1)]
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.peek().clone();
+        self.pos += 1;
+        token
+    }
+}
+
+fn error(token: &Token, message: &str) -> ParseError {
+    let message = match &token.kind {
+        TokenKind::Word(word) => format!("{}, found \"{}\"", message, word),
+        TokenKind::OpenBrace => format!("{}, found {{", message),
+        TokenKind::CloseBrace => format!("{}, found }}", message),
+        TokenKind::Newline => format!("{}, found end of line", message),
+    };
+
+    ParseError {
+        line: token.line,
+        column: token.column,
+        message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn caller(name: &str, groups: &[&str]) -> Caller {
+        Caller {
+            name: name.to_string(),
+            groups: groups.iter().map(|group| group.to_string()).collect(),
+        }
+    }
+
+    fn request(caller: Caller, target: &str, command: &[&str]) -> Request {
+        Request {
+            caller,
+            target: target.to_string(),
+            command: command.iter().map(OsString::from).collect(),
+            edit: false,
+            hosts: vec!["db1.example.com".to_string(), "db1".to_string()],
+        }
+    }
+
+    /// Pretends these are the only executables, so rules are not matched against this machine.
+    fn resolve(command: &OsStr, search_path: &OsStr) -> io::Result<PathBuf> {
+        let files = ["/usr/bin/id", "/usr/bin/systemctl", "/bin/ls", "/bin/rm"];
+        std::env::split_paths(search_path)
+            .map(|dir| dir.join(command))
+            .find(|path| files.iter().any(|file| path == Path::new(file)))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "command not found"))
+    }
+
+    fn line(decision: Decision) -> Option<usize> {
+        match decision {
+            Decision::Permit(rule) => Some(rule.line),
+            Decision::Deny(_) => None,
+        }
+    }
+
+    #[test]
+    fn parses_a_full_rule() {
+        let config = Config::parse(
+            "permit nopass persist=60 keepenv setenv { FOO=bar -BAZ QUX } alice as root cmd /bin/ls args -l /\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.rules,
+            vec![Rule {
+                action: Action::Permit,
+                options: Options {
+                    nopass: true,
+                    persist: Some(Duration::from_secs(60)),
+                    keepenv: true,
+                    setenv: vec![
+                        EnvEntry::Set("FOO".to_string(), "bar".to_string()),
+                        EnvEntry::Remove("BAZ".to_string()),
+                        EnvEntry::Keep("QUX".to_string()),
+                    ],
+                    nolog: false,
+                    nolog_failure: false,
+                },
+                identity: Identity::User("alice".to_string()),
+                target: Some("root".to_string()),
+                host: None,
+                cmd: Some("/bin/ls".to_string()),
+                args: Some(vec!["-l".to_string(), "/".to_string()]),
+                edit: None,
+                line: 1,
+                file: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn handles_comments_quotes_and_continuations() {
+        let config = Config::parse(
+            "# admins\n\npermit :wheel \\\n  cmd \"/opt/my tool\" args \"a b\" # trailing\n",
+        )
+        .unwrap();
+
+        let rule = &config.rules[0];
+        assert_eq!(rule.identity, Identity::Group("wheel".to_string()));
+        assert_eq!(rule.cmd.as_deref(), Some("/opt/my tool"));
+        assert_eq!(rule.args, Some(vec!["a b".to_string()]));
+        assert_eq!(rule.line, 3);
+    }
+
+    #[test]
+    fn setenv_blocks_may_span_lines() {
+        let config = Config::parse("permit setenv {\n  FOO=bar\n  -BAZ\n} alice\n").unwrap();
+        assert_eq!(
+            config.rules[0].options.setenv,
+            vec![
+                EnvEntry::Set("FOO".to_string(), "bar".to_string()),
+                EnvEntry::Remove("BAZ".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn args_alone_allows_no_arguments() {
+        let config = Config::parse("permit alice cmd /bin/ls args\n").unwrap();
+        assert_eq!(config.rules[0].args, Some(vec![]));
+
+        let alice = caller("alice", &[]);
+        assert!(matches!(
+            config.evaluate(&request(alice.clone(), "root", &["/bin/ls"]), resolve),
+            Decision::Permit(_)
+        ));
+        assert!(matches!(
+            config.evaluate(&request(alice, "root", &["/bin/ls", "-l"]), resolve),
+            Decision::Deny(None)
+        ));
+    }
+
+    #[test]
+    fn reports_where_errors_are() {
+        let err = Config::parse("permit alice\nallow bob\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "2:1: expected permit or deny, found \"allow\""
+        );
+
+        let err = Config::parse("permit alice \"bob\n").unwrap_err();
+        assert_eq!(err.to_string(), "1:14: unterminated quote");
+
+        let err = Config::parse("permit setenv FOO alice\n").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 15));
+
+        let err = Config::parse("permit alice cmd bin/ls\n").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 18));
+
+        assert!(Config::parse("permit\n").is_err());
+        assert!(Config::parse("permit :\n").is_err());
+        assert!(Config::parse("permit setenv { FOO alice\n").is_err());
+        assert!(Config::parse("permit setenv { =bar } alice\n").is_err());
+        assert!(Config::parse("permit alice extra\n").is_err());
+        assert!(Config::parse("permit alice \\").is_err());
+    }
+
+    #[test]
+    fn parses_settings() {
+        let config = Config::parse(
+            "set max_failures 3\nset lockout 60\nset secure_path /usr/bin:/bin\npermit alice\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.settings,
+            Settings {
+                max_failures: 3,
+                lockout: Duration::from_secs(60),
+                secure_path: "/usr/bin:/bin".to_string(),
+            }
+        );
+        assert_eq!(config.rules.len(), 1);
+
+        assert!(Config::parse("set max_failures 0\n").is_err());
+        assert!(Config::parse("set lockout soon\n").is_err());
+        assert!(Config::parse("set retries 3\n").is_err());
+        assert!(Config::parse("set secure_path /usr/bin:bin\n").is_err());
+        assert!(Config::parse("set secure_path /usr/bin::/bin\n").is_err());
+    }
+
+    #[test]
+    fn last_matching_rule_wins() {
+        let config = Config::parse(
+            "permit alice\ndeny alice cmd /bin/rm\npermit alice cmd /bin/rm args -i\n",
+        )
+        .unwrap();
+        let alice = caller("alice", &[]);
+
+        assert_eq!(
+            line(config.evaluate(&request(alice.clone(), "root", &["/bin/ls"]), resolve)),
+            Some(1)
+        );
+        assert_eq!(
+            line(config.evaluate(
+                &request(alice.clone(), "root", &["/bin/rm", "-rf"]),
+                resolve
+            )),
+            None
+        );
+        assert_eq!(
+            line(config.evaluate(&request(alice, "root", &["/bin/rm", "-i"]), resolve)),
+            Some(3)
+        );
+        assert_eq!(
+            config.evaluate(&request(caller("bob", &[]), "root", &["/bin/ls"]), resolve),
+            Decision::Deny(None)
+        );
+    }
+
+    #[test]
+    fn matches_groups_and_targets() {
+        let config = Config::parse("permit :wheel as root\n").unwrap();
+
+        assert!(matches!(
+            config.evaluate(
+                &request(caller("bob", &["users", "wheel"]), "root", &["/bin/ls"]),
+                resolve
+            ),
+            Decision::Permit(_)
+        ));
+        assert!(matches!(
+            config.evaluate(
+                &request(caller("bob", &["users"]), "root", &["/bin/ls"]),
+                resolve
+            ),
+            Decision::Deny(None)
+        ));
+        assert!(matches!(
+            config.evaluate(
+                &request(caller("bob", &["wheel"]), "alice", &["/bin/ls"]),
+                resolve
+            ),
+            Decision::Deny(None)
+        ));
+        // a user named like the group is not in it
+        assert!(matches!(
+            config.evaluate(
+                &request(caller("wheel", &[]), "root", &["/bin/ls"]),
+                resolve
+            ),
+            Decision::Deny(None)
+        ));
+    }
+
+    #[test]
+    fn matches_commands_by_path_or_name() {
+        let config =
+            Config::parse("permit alice cmd systemctl\npermit bob cmd /usr/bin/id args\n").unwrap();
+
+        let alice = caller("alice", &[]);
+        assert!(matches!(
+            config.evaluate(
+                &request(alice.clone(), "root", &["/usr/bin/systemctl", "restart"]),
+                resolve
+            ),
+            Decision::Permit(_)
+        ));
+        // a program with the same name somewhere else is not the one the rule means
+        assert!(matches!(
+            config.evaluate(
+                &request(alice.clone(), "root", &["/home/alice/systemctl"]),
+                resolve
+            ),
+            Decision::Deny(None)
+        ));
+        assert!(matches!(
+            config.evaluate(&request(alice, "root", &[]), resolve),
+            Decision::Deny(None)
+        ));
+
+        let bob = caller("bob", &[]);
+        assert!(matches!(
+            config.evaluate(&request(bob.clone(), "root", &["/usr/bin/id"]), resolve),
+            Decision::Permit(_)
+        ));
+        assert!(matches!(
+            config.evaluate(
+                &request(bob.clone(), "root", &["/usr/bin/id", "-u"]),
+                resolve
+            ),
+            Decision::Deny(None)
+        ));
+        assert!(matches!(
+            config.evaluate(&request(bob, "root", &["/tmp/usr/bin/id"]), resolve),
+            Decision::Deny(None)
+        ));
+    }
+
+    #[test]
+    fn edit_rules_match_only_their_files() {
+        let config = Config::parse("permit alice edit /etc/motd /etc/../etc/issue\n").unwrap();
+        assert_eq!(
+            config.rules[0].edit,
+            Some(vec![
+                "/etc/motd".to_string(),
+                "/etc/../etc/issue".to_string()
+            ])
+        );
+
+        let edit = |files: &[&str]| Request {
+            edit: true,
+            ..request(caller("alice", &[]), "root", files)
+        };
+        assert!(matches!(
+            config.evaluate(&edit(&["/etc/motd", "/etc/issue"]), resolve),
+            Decision::Permit(_)
+        ));
+        assert!(matches!(
+            config.evaluate(&edit(&["/etc/motd", "/etc/shadow"]), resolve),
+            Decision::Deny(None)
+        ));
+        // editing is not running the file
+        assert!(matches!(
+            config.evaluate(
+                &request(caller("alice", &[]), "root", &["/etc/motd"]),
+                resolve
+            ),
+            Decision::Deny(None)
+        ));
+
+        assert!(Config::parse("permit alice edit\n").is_err());
+        assert!(Config::parse("permit alice edit motd\n").is_err());
+        assert!(Config::parse("permit alice cmd vi edit /etc/motd\n").is_err());
+    }
+
+    #[test]
+    fn secure_path_limits_bare_names() {
+        let config =
+            Config::parse("set secure_path /tmp:/usr/bin/\npermit alice cmd id\n").unwrap();
+        let alice = caller("alice", &[]);
+
+        assert!(matches!(
+            config.evaluate(&request(alice.clone(), "root", &["/usr/bin/id"]), resolve),
+            Decision::Permit(_)
+        ));
+        assert!(matches!(
+            config.evaluate(&request(alice, "root", &["/home/alice/bin/id"]), resolve),
+            Decision::Deny(None)
+        ));
+    }
+
+    #[test]
+    fn host_rules_match_any_name_of_the_machine() {
+        let config = Config::parse(
+            "permit alice on web*\npermit bob on DB?.example.com\npermit carol on db1\n",
+        )
+        .unwrap();
+        assert_eq!(config.rules[0].host.as_deref(), Some("web*"));
+
+        let permitted = |name: &str| {
+            matches!(
+                config.evaluate(&request(caller(name, &[]), "root", &["/bin/ls"]), resolve),
+                Decision::Permit(_)
+            )
+        };
+        assert!(!permitted("alice"));
+        assert!(permitted("bob"));
+        assert!(permitted("carol"));
+
+        assert!(Config::parse("permit alice on\n").is_err());
+    }
+
+    #[test]
+    fn globs_match_whole_names() {
+        assert!(glob("db*", "db1.example.com"));
+        assert!(glob("*.example.com", "db1.example.com"));
+        assert!(glob("db?-*-prod", "db1-eu-prod"));
+        assert!(glob("*", ""));
+        assert!(!glob("db*", "webdb1"));
+        assert!(!glob("db?", "db12"));
+        assert!(!glob("*.example.com", "example.com"));
+    }
+
+    /// A fresh directory with the given files in it, their contents have `DIR` replaced by its
+    /// path.
+    fn fixture(name: &str, files: &[(&str, &str)]) -> TempDir {
+        let dir = TempDir::new(&format!("config-{}", name));
+        for (file, text) in files {
+            let file = dir.join(file);
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(file, text.replace("DIR", dir.to_str().unwrap())).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn includes_are_loaded_in_place() {
+        let dir = fixture(
+            "include",
+            &[
+                (
+                    "execas.conf",
+                    "permit alice\nincludedir DIR/execas.d\ninclude DIR/last\n",
+                ),
+                ("execas.d/20-db", "set lockout 60\ndeny alice cmd rm\n"),
+                ("execas.d/10-web", "permit bob\n"),
+                ("execas.d/.hidden", "permit mallory\n"),
+                ("execas.d/10-web~", "permit mallory\n"),
+                ("execas.d/30-old/rules", "permit mallory\n"),
+                ("last", "permit carol\n"),
+            ],
+        );
+        std::os::unix::fs::symlink(dir.join("last"), dir.join("execas.d/40-link")).unwrap();
+
+        let config = Config::load_unchecked(&dir.join("execas.conf")).unwrap();
+        let rules: Vec<(String, Option<PathBuf>)> = config
+            .rules
+            .iter()
+            .map(|rule| (rule.identity.to_string(), rule.file.clone()))
+            .collect();
+        assert_eq!(
+            rules,
+            vec![
+                ("alice".to_string(), None),
+                ("bob".to_string(), Some(dir.join("execas.d/10-web"))),
+                ("alice".to_string(), Some(dir.join("execas.d/20-db"))),
+                ("carol".to_string(), Some(dir.join("last"))),
+            ]
+        );
+        assert_eq!(config.settings.lockout, Duration::from_secs(60));
+        assert_eq!(
+            config.rules[2].location(),
+            format!("line 2 of {}", dir.join("execas.d/20-db").display())
+        );
+    }
+
+    #[test]
+    fn include_errors_name_the_file_and_line() {
+        let dir = fixture(
+            "include-errors",
+            &[
+                ("main", "permit alice\ninclude DIR/bad\n"),
+                ("bad", "permit bob\nallow carol\n"),
+                ("missing", "\ninclude DIR/nope\n"),
+                ("a", "include DIR/b\n"),
+                ("b", "permit bob\ninclude DIR/a\n"),
+            ],
+        );
+        let load = |name: &str| {
+            Config::load_unchecked(&dir.join(name))
+                .unwrap_err()
+                .to_string()
+                .replace(dir.to_str().unwrap(), "DIR")
+        };
+
+        assert_eq!(
+            load("main"),
+            "DIR/bad:2:1: expected permit or deny, found \"allow\""
+        );
+        assert_eq!(
+            load("missing"),
+            "DIR/missing:2: DIR/nope: No such file or directory (os error 2)"
+        );
+        assert_eq!(load("a"), "DIR/b:2:1: DIR/a includes itself");
+
+        assert!(Config::parse("include /etc/execas.d/db\n").is_err());
+        assert!(Config::load_unchecked(&dir.join("nothing")).is_err());
+        fs::write(dir.join("relative"), "include execas.d\n").unwrap();
+        assert!(Config::load_unchecked(&dir.join("relative")).is_err());
+    }
+
+    #[test]
+    fn lists_only_the_callers_rules() {
+        let config = Config::parse("permit alice\npermit :staff cmd ls\npermit bob\n").unwrap();
+        let lines: Vec<usize> = config
+            .rules_for(&caller("alice", &["staff"]), &[])
+            .map(|rule| rule.line)
+            .collect();
+        assert_eq!(lines, vec![1, 2]);
+    }
+
+    #[test]
+    fn displays_rules_as_they_parse() {
+        let text = "permit nopass persist=60 setenv { FOO=bar -BAZ } :wheel as root on \"db*\" cmd \"/opt/my tool\" args \"a b\" \"\"\npermit alice edit /etc/motd \"/etc/my file\"";
+        let config = Config::parse(text).unwrap();
+        for rule in &config.rules {
+            let shown = rule.to_string();
+            assert_eq!(
+                Config::parse(&shown).unwrap().rules[0],
+                Rule {
+                    line: 1,
+                    ..rule.clone()
+                }
+            );
+        }
+    }
+}
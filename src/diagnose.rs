@@ -0,0 +1,209 @@
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// A reason execas could be running without root privileges.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Problem {
+    NotOwnedByRoot,
+    NoSetuidBit,
+    NosuidMount,
+    NoNewPrivs,
+    UserNamespace,
+    Unknown,
+}
+
+impl Problem {
+    /// Each problem exits with its own status so scripts can tell them apart.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Problem::NotOwnedByRoot => 10,
+            Problem::NoSetuidBit => 11,
+            Problem::NosuidMount => 12,
+            Problem::NoNewPrivs => 13,
+            Problem::UserNamespace => 14,
+            Problem::Unknown => 15,
+        }
+    }
+
+    /// What went wrong and how to fix it.
+    pub fn describe(self, exe: &Path) -> String {
+        let exe = exe.display();
+        match self {
+            Problem::NotOwnedByRoot => format!(
+                "{} is not owned by root, fix it with `chown root {}` and set the setuid bit again",
+                exe, exe
+            ),
+            Problem::NoSetuidBit => format!(
+                "{} does not have the setuid bit set, fix it with `chmod u+s {}`",
+                exe, exe
+            ),
+            Problem::NosuidMount => format!(
+                "{} is on a filesystem mounted with nosuid, install it somewhere else or remount without nosuid",
+                exe
+            ),
+            Problem::NoNewPrivs => "the process has no_new_privs set so the setuid bit is ignored, \
+                run execas from outside the sandbox or service that set it"
+                .to_string(),
+            Problem::UserNamespace => "the process is in a user namespace where the setuid bit can not grant real root, \
+                run execas from the initial user namespace"
+                .to_string(),
+            Problem::Unknown => format!(
+                "{} should be running as root but is not and no reason could be found",
+                exe
+            ),
+        }
+    }
+}
+
+/// Work out why execas is not running as root.
+///
+/// The returned problems are in the order they should be fixed, it is never empty.
+pub fn diagnose() -> (PathBuf, Vec<Problem>) {
+    let exe = fs::read_link("/proc/self/exe").unwrap_or_else(|_| PathBuf::from("execas"));
+    let mut problems = Vec::new();
+
+    if let Ok(metadata) = fs::metadata(&exe) {
+        if metadata.uid() != 0 {
+            problems.push(Problem::NotOwnedByRoot);
+        }
+        if metadata.mode() & libc::S_ISUID == 0 {
+            problems.push(Problem::NoSetuidBit);
+        }
+    }
+
+    // anything that can not be read is assumed not to be the problem
+    let read = |path: &str| fs::read_to_string(path).ok();
+
+    if read("/proc/self/mountinfo").is_some_and(|mountinfo| on_nosuid_mount(&exe, &mountinfo)) {
+        problems.push(Problem::NosuidMount);
+    }
+
+    if read("/proc/self/status").is_some_and(|status| no_new_privs(&status)) {
+        problems.push(Problem::NoNewPrivs);
+    }
+
+    if read("/proc/self/uid_map").is_some_and(|uid_map| in_user_namespace(&uid_map)) {
+        problems.push(Problem::UserNamespace);
+    }
+
+    if problems.is_empty() {
+        problems.push(Problem::Unknown);
+    }
+
+    (exe, problems)
+}
+
+/// Find the mount containing `path` in `mountinfo` and check whether it has the nosuid option.
+fn on_nosuid_mount(path: &Path, mountinfo: &str) -> bool {
+    // fields are: id parent major:minor root mount-point options ... the deepest and latest mount wins
+    let mut best: Option<(usize, bool)> = None;
+    for line in mountinfo.lines() {
+        let fields: Vec<&str> = line.split(' ').collect();
+        if fields.len() < 6 {
+            continue;
+        }
+
+        let mount_point = PathBuf::from(unescape(fields[4]));
+        if !path.starts_with(&mount_point) {
+            continue;
+        }
+
+        let depth = mount_point.components().count();
+        let nosuid = fields[5].split(',').any(|option| option == "nosuid");
+        if best.is_none_or(|(best_depth, _)| depth >= best_depth) {
+            best = Some((depth, nosuid));
+        }
+    }
+
+    best.is_some_and(|(_, nosuid)| nosuid)
+}
+
+/// Mount points escape spaces, tabs, newlines and backslashes as octal.
+fn unescape(field: &str) -> String {
+    let mut out = String::with_capacity(field.len());
+    let mut chars = field.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            let octal: String = chars.by_ref().take(3).collect();
+            if let Ok(byte) = u8::from_str_radix(&octal, 8) {
+                out.push(byte as char);
+                continue;
+            }
+            out.push(c);
+            out.push_str(&octal);
+        } else {
+            out.push(c);
+        }
+    }
+
+    out
+}
+
+/// Whether /proc/self/`status` has no_new_privs set.
+fn no_new_privs(status: &str) -> bool {
+    status.lines().any(|line| {
+        line.strip_prefix("NoNewPrivs:")
+            .is_some_and(|value| value.trim() == "1")
+    })
+}
+
+/// Whether `uid_map` is not that of the initial user namespace, which maps every id onto itself.
+fn in_user_namespace(uid_map: &str) -> bool {
+    let fields: Vec<&str> = uid_map.split_whitespace().collect();
+    fields != ["0", "0", "4294967295"]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOUNTINFO: &str = "\
+22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+30 22 8:2 / /home rw,nosuid,nodev shared:2 - ext4 /dev/sda2 rw
+31 30 8:3 / /home/alice/bin rw,relatime shared:3 - ext4 /dev/sda3 rw
+32 22 0:40 / /mnt/usb\\040stick rw,nosuid - vfat /dev/sdb1 rw
+33 22 0:41 / /opt rw,nosuid - tmpfs tmpfs rw
+34 22 0:42 / /opt rw - tmpfs tmpfs rw
+";
+
+    #[test]
+    fn unescapes_octal_in_mount_points() {
+        assert_eq!(unescape("/mnt/usb\\040stick"), "/mnt/usb stick");
+        assert_eq!(unescape("/a\\011b\\012c\\134d"), "/a\tb\nc\\d");
+        // anything that is not three octal digits is kept as it is
+        assert_eq!(unescape("/a\\9xyz"), "/a\\9xyz");
+        assert_eq!(unescape("/plain"), "/plain");
+    }
+
+    #[test]
+    fn the_deepest_mount_decides() {
+        let nosuid = |path: &str| on_nosuid_mount(Path::new(path), MOUNTINFO);
+
+        assert!(!nosuid("/usr/bin/execas"));
+        assert!(nosuid("/home/bob/execas"));
+        assert!(!nosuid("/home/alice/bin/execas"));
+        // components are compared whole, /homework is not under /home
+        assert!(!nosuid("/homework/execas"));
+        assert!(nosuid("/mnt/usb stick/execas"));
+        // of two mounts on the same point the later one is on top
+        assert!(!nosuid("/opt/execas"));
+        assert!(!on_nosuid_mount(Path::new("/usr/bin/execas"), ""));
+    }
+
+    #[test]
+    fn detects_user_namespaces() {
+        assert!(!in_user_namespace("         0          0 4294967295\n"));
+        assert!(in_user_namespace("         0       1000          1\n"));
+        assert!(in_user_namespace(
+            "         0     100000      65536\n     65536       1000          1\n"
+        ));
+    }
+
+    #[test]
+    fn reads_no_new_privs_from_status() {
+        assert!(no_new_privs("Name:\texecas\nNoNewPrivs:\t1\nSeccomp:\t0\n"));
+        assert!(!no_new_privs("Name:\texecas\nNoNewPrivs:\t0\n"));
+    }
+}
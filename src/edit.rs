@@ -0,0 +1,312 @@
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::FromRawFd;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use nix::unistd::{access, mkstemp, AccessFlags, User};
+
+use crate::error::Error;
+use crate::run::Edit;
+use crate::user;
+
+/// Where the callers copies are made, unlike /tmp it is not cleaned while an editor is open.
+const TEMP_TEMPLATE: &str = "/var/tmp/execas.XXXXXX";
+
+/// A file being edited and the callers copy of it.
+struct Draft<'a> {
+    file: &'a Path,
+    /// `None` when the file does not exist yet.
+    original: Option<Vec<u8>>,
+    copy: PathBuf,
+}
+
+impl Draft<'_> {
+    /// Whether `edited` leaves the file as it was, a new file left empty is not created.
+    fn unchanged(&self, edited: &[u8]) -> bool {
+        match &self.original {
+            Some(original) => original == edited,
+            None => edited.is_empty(),
+        }
+    }
+}
+
+/// Let `user` edit copies of the files with their own privileges and write the changed ones back
+/// as `target`.
+///
+/// Symlinks and files in directories `user` can write to are refused, they could be swapped for
+/// another file while the editor is open.
+pub fn edit(user: &User, target: &User, edit: &Edit) -> Result<i32, Error> {
+    let mut drafts = Vec::new();
+    for file in &edit.files {
+        match draft(user, target, file) {
+            Ok(draft) => drafts.push(draft),
+            Err(err) => {
+                remove_copies(user, &drafts);
+                return Err(err);
+            }
+        }
+    }
+
+    if let Err(err) = run_editor(user, &edit.editor, &drafts) {
+        remove_copies(user, &drafts);
+        return Err(err);
+    }
+
+    let mut result = Ok(0);
+    for draft in &drafts {
+        match save(user, target, draft) {
+            Ok(()) => remove_copies(user, std::slice::from_ref(draft)),
+            Err(err) => {
+                // keep the copy so the changes are not lost
+                eprintln!(
+                    "execas: {}, the changes are kept in {}",
+                    err,
+                    draft.copy.display()
+                );
+                result = Err(Error::Failed(format!(
+                    "{} was not saved",
+                    draft.file.display()
+                )));
+            }
+        }
+    }
+    result
+}
+
+/// Read `file` as `target` and give `user` a private copy of it.
+fn draft<'a>(user: &User, target: &User, file: &'a Path) -> Result<Draft<'a>, Error> {
+    check_directories(user, file)?;
+
+    let original = switch(target, || read_original(file))?
+        .map_err(|err| Error::Failed(format!("{}: {}", file.display(), err)))?;
+    let copy = switch(user, || make_copy(original.as_deref().unwrap_or_default()))?
+        .map_err(|err| Error::Failed(format!("failed to copy {}: {}", file.display(), err)))?;
+
+    Ok(Draft {
+        file,
+        original,
+        copy,
+    })
+}
+
+/// Run the editor on the copies as `user`, it is their own program so it gets nothing more.
+fn run_editor(user: &User, editor: &[OsString], drafts: &[Draft]) -> Result<(), Error> {
+    let name = editor[0].to_string_lossy();
+    let mut command = Command::new(&editor[0]);
+    command
+        .args(&editor[1..])
+        .args(drafts.iter().map(|draft| &draft.copy));
+
+    let user = user.clone();
+    unsafe {
+        command.pre_exec(move || user::become_user(&user).map_err(io::Error::from));
+    }
+
+    let status = command
+        .status()
+        .map_err(|err| Error::Failed(format!("{}: {}", name, err)))?;
+    if !status.success() {
+        return Err(Error::Failed(format!(
+            "{} failed ({}), nothing was saved",
+            name, status
+        )));
+    }
+
+    Ok(())
+}
+
+/// Write the copy back over the file as `target` if it was changed.
+fn save(user: &User, target: &User, draft: &Draft) -> Result<(), Error> {
+    let edited = switch(user, || read_file(&draft.copy))?
+        .map_err(|err| Error::Failed(format!("{}: {}", draft.copy.display(), err)))?;
+
+    if draft.unchanged(&edited) {
+        eprintln!("execas: {} unchanged", draft.file.display());
+        return Ok(());
+    }
+
+    // the directories could have changed while the editor was open
+    check_directories(user, draft.file)?;
+    switch(target, || overwrite(draft.file, &edited))?
+        .map_err(|err| Error::Failed(format!("failed to write {}: {}", draft.file.display(), err)))
+}
+
+/// Refuse `file` when `user` could replace it or one of the directories leading to it.
+fn check_directories(user: &User, file: &Path) -> Result<(), Error> {
+    // root can replace anything anyway
+    if user.uid.is_root() {
+        return Ok(());
+    }
+
+    // access checks the real ids, which are still the callers
+    for dir in file.ancestors().skip(1) {
+        if access(dir, AccessFlags::W_OK).is_ok() {
+            return Err(Error::Failed(format!(
+                "{}: {} is writable by {}",
+                file.display(),
+                dir.display(),
+                user.name
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// The contents of `file`, `None` if it does not exist yet.
+fn read_original(file: &Path) -> io::Result<Option<Vec<u8>>> {
+    match read_file(file) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+fn read_file(file: &Path) -> io::Result<Vec<u8>> {
+    let mut opened = open_regular(file, OpenOptions::new().read(true))?;
+    let mut contents = Vec::new();
+    opened.read_to_end(&mut contents)?;
+    Ok(contents)
+}
+
+/// Create a copy only the current effective user can access.
+fn make_copy(contents: &[u8]) -> io::Result<PathBuf> {
+    let (fd, path) = mkstemp(TEMP_TEMPLATE)?;
+    let mut copy = unsafe { File::from_raw_fd(fd) };
+
+    if let Err(err) = copy.write_all(contents) {
+        let _ = fs::remove_file(&path);
+        return Err(err);
+    }
+
+    Ok(path)
+}
+
+/// Replace the contents of `file` in place so its owner and mode stay the same.
+fn overwrite(file: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut opened = open_regular(
+        file,
+        OpenOptions::new().write(true).create(true).mode(0o644),
+    )?;
+    opened.set_len(0)?;
+    opened.write_all(contents)
+}
+
+/// Open `file` without following a symlink, it has to be a regular file.
+fn open_regular(file: &Path, options: &mut OpenOptions) -> io::Result<File> {
+    // nonblocking so a fifo can not hang us before it is rejected
+    let opened = options
+        .custom_flags(libc::O_NOFOLLOW | libc::O_CLOEXEC | libc::O_NONBLOCK)
+        .open(file)
+        .map_err(|err| match err.raw_os_error() {
+            Some(libc::ELOOP) => io::Error::new(io::ErrorKind::PermissionDenied, "is a symlink"),
+            _ => err,
+        })?;
+
+    if !opened.metadata()?.is_file() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "is not a regular file",
+        ));
+    }
+
+    Ok(opened)
+}
+
+fn remove_copies(user: &User, drafts: &[Draft]) {
+    let _ = user::as_user(user, || {
+        for draft in drafts {
+            let _ = fs::remove_file(&draft.copy);
+        }
+    });
+}
+
+/// Run `f` as `user`, failing if the ids can not be switched.
+fn switch<T>(user: &User, f: impl FnOnce() -> T) -> Result<T, Error> {
+    user::as_user(user, f)
+        .map_err(|err| Error::Failed(format!("failed to switch to user {}: {}", user.name, err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use nix::sys::stat::Mode;
+    use nix::unistd::mkfifo;
+
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn fixture(name: &str) -> TempDir {
+        let dir = TempDir::new(&format!("edit-{}", name));
+
+        fs::write(dir.join("file"), "original\n").unwrap();
+        symlink(dir.join("file"), dir.join("link")).unwrap();
+        mkfifo(&dir.join("fifo"), Mode::from_bits_truncate(0o600)).unwrap();
+
+        dir
+    }
+
+    fn message(err: io::Error) -> String {
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        err.to_string()
+    }
+
+    #[test]
+    fn symlinks_are_refused() {
+        let dir = fixture("symlink");
+
+        let err = read_original(&dir.join("link")).unwrap_err();
+        assert_eq!(message(err), "is a symlink");
+
+        // writing through the link must not reach the file it points to
+        let err = overwrite(&dir.join("link"), b"changed\n").unwrap_err();
+        assert_eq!(message(err), "is a symlink");
+        assert_eq!(fs::read(dir.join("file")).unwrap(), b"original\n");
+    }
+
+    #[test]
+    fn fifos_are_refused_without_blocking() {
+        let dir = fixture("fifo");
+
+        let err = read_original(&dir.join("fifo")).unwrap_err();
+        assert_eq!(message(err), "is not a regular file");
+    }
+
+    #[test]
+    fn regular_files_are_read_and_overwritten() {
+        let dir = fixture("regular");
+
+        assert_eq!(
+            read_original(&dir.join("file")).unwrap(),
+            Some(b"original\n".to_vec())
+        );
+        assert_eq!(read_original(&dir.join("missing")).unwrap(), None);
+
+        overwrite(&dir.join("file"), b"new\n").unwrap();
+        assert_eq!(fs::read(dir.join("file")).unwrap(), b"new\n");
+    }
+
+    #[test]
+    fn unchanged_files_are_detected() {
+        let existing = Draft {
+            file: Path::new("/etc/motd"),
+            original: Some(b"hello\n".to_vec()),
+            copy: PathBuf::from("/var/tmp/execas.test"),
+        };
+        assert!(existing.unchanged(b"hello\n"));
+        assert!(!existing.unchanged(b"hello\nworld\n"));
+        assert!(!existing.unchanged(b""));
+
+        let new = Draft {
+            original: None,
+            ..existing
+        };
+        assert!(new.unchanged(b""));
+        assert!(!new.unchanged(b"hello\n"));
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::BTreeMap;
+use std::ffi::{CString, OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+use nix::unistd::User;
+
+use crate::config::{EnvEntry, Options};
+
+/// Variables carried over from the caller when the rule does not use `keepenv`.
+const SAFE_VARS: [&str; 5] = ["COLORTERM", "DISPLAY", "LANG", "LANGUAGE", "TERM"];
+
+/// Locale variables are harmless too, they all share this prefix.
+const SAFE_PREFIX: &str = "LC_";
+
+/// The default `secure_path`, commands are looked up in it and get it as their `PATH`.
+pub const SAFE_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// Build the environment for the command from the caller's `current` environment.
+///
+/// Only a small set of variables survive unless the rule has `keepenv`, the identity variables always
+/// describe the target and the rule's `setenv` entries are applied last. `PATH` is `search_path`
+/// unless `keepenv` kept the callers.
+pub fn build<I>(
+    options: &Options,
+    caller: &User,
+    target: &User,
+    current: I,
+    search_path: &str,
+) -> Vec<CString>
+where
+    I: IntoIterator<Item = (OsString, OsString)>,
+{
+    let current: BTreeMap<OsString, OsString> = current.into_iter().collect();
+
+    let mut env: BTreeMap<OsString, OsString> = if options.keepenv {
+        current.clone()
+    } else {
+        current
+            .iter()
+            .filter(|(name, _)| is_safe(name))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect()
+    };
+
+    if !options.keepenv || !env.contains_key(OsStr::new("PATH")) {
+        env.insert("PATH".into(), search_path.into());
+    }
+    env.insert("HOME".into(), target.dir.clone().into_os_string());
+    env.insert("LOGNAME".into(), target.name.clone().into());
+    env.insert("USER".into(), target.name.clone().into());
+    env.insert("SHELL".into(), target.shell.clone().into_os_string());
+    env.insert("EXECAS_USER".into(), caller.name.clone().into());
+
+    for entry in &options.setenv {
+        match entry {
+            // a value starting with $ copies another of the caller's variables
+            EnvEntry::Set(name, value) => match value.strip_prefix('$') {
+                Some(from) => match current.get(OsStr::new(from)) {
+                    Some(value) => {
+                        env.insert(name.into(), value.clone());
+                    }
+                    None => {
+                        env.remove(OsStr::new(name));
+                    }
+                },
+                None => {
+                    env.insert(name.into(), value.into());
+                }
+            },
+            EnvEntry::Remove(name) => {
+                env.remove(OsStr::new(name));
+            }
+            EnvEntry::Keep(name) => {
+                if let Some(value) = current.get(OsStr::new(name)) {
+                    env.insert(name.into(), value.clone());
+                }
+            }
+        }
+    }
+
+    env.into_iter()
+        .filter_map(|(name, value)| {
+            let mut pair = name.into_vec();
+            pair.push(b'=');
+            pair.extend(value.into_vec());
+            CString::new(pair).ok()
+        })
+        .collect()
+}
+
+fn is_safe(name: &OsStr) -> bool {
+    let name = name.as_bytes();
+    SAFE_VARS.iter().any(|safe| safe.as_bytes() == name) || name.starts_with(SAFE_PREFIX.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use nix::unistd::{Gid, Uid};
+
+    use super::*;
+
+    fn user(name: &str) -> User {
+        User {
+            name: name.to_string(),
+            passwd: CString::default(),
+            uid: Uid::from_raw(1000),
+            gid: Gid::from_raw(1000),
+            gecos: CString::default(),
+            dir: PathBuf::from(format!("/home/{}", name)),
+            shell: PathBuf::from("/bin/sh"),
+        }
+    }
+
+    fn build_with(options: &Options, current: &[(&str, &str)]) -> Vec<String> {
+        build_in(options, current, SAFE_PATH)
+    }
+
+    fn build_in(options: &Options, current: &[(&str, &str)], search_path: &str) -> Vec<String> {
+        let current = current
+            .iter()
+            .map(|(name, value)| (OsString::from(name), OsString::from(value)));
+        build(options, &user("alice"), &user("root"), current, search_path)
+            .into_iter()
+            .map(|pair| pair.into_string().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn keepenv_keeps_the_callers_path() {
+        let options = Options {
+            keepenv: true,
+            ..Options::default()
+        };
+        let env = build_with(&options, &[("PATH", "/opt/bin"), ("EDITOR", "vi")]);
+        assert!(env.contains(&"PATH=/opt/bin".to_string()));
+        assert!(env.contains(&"EDITOR=vi".to_string()));
+        assert!(env.contains(&"HOME=/home/root".to_string()));
+    }
+
+    #[test]
+    fn path_is_the_search_path() {
+        let env = build_in(
+            &Options::default(),
+            &[("PATH", "/home/alice/bin")],
+            "/usr/bin:/bin",
+        );
+        assert!(env.contains(&"PATH=/usr/bin:/bin".to_string()));
+    }
+
+    #[test]
+    fn setenv_is_applied_last() {
+        let options = Options {
+            setenv: vec![
+                EnvEntry::Set("FOO".to_string(), "bar".to_string()),
+                EnvEntry::Set("WHERE".to_string(), "$PWD".to_string()),
+                EnvEntry::Set("GONE".to_string(), "$MISSING".to_string()),
+                EnvEntry::Keep("EDITOR".to_string()),
+                EnvEntry::Remove("TERM".to_string()),
+            ],
+            ..Options::default()
+        };
+        let env = build_with(
+            &options,
+            &[
+                ("PWD", "/srv"),
+                ("EDITOR", "vi"),
+                ("TERM", "xterm"),
+                ("LC_ALL", "C"),
+            ],
+        );
+
+        assert_eq!(
+            env,
+            vec![
+                "EDITOR=vi",
+                "EXECAS_USER=alice",
+                "FOO=bar",
+                "HOME=/home/root",
+                "LC_ALL=C",
+                "LOGNAME=root",
+                &format!("PATH={}", SAFE_PATH),
+                "SHELL=/bin/sh",
+                "USER=root",
+                "WHERE=/srv",
+            ]
+        );
+    }
+}
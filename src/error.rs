@@ -0,0 +1,116 @@
+use std::fmt;
+use std::io;
+
+use crate::config::LoadError;
+
+/// Why execas did not run the command, each kind has its own exit status.
+#[derive(Debug)]
+pub enum Error {
+    /// The config does not permit the request.
+    Denied,
+    /// The caller could not be authenticated.
+    Authentication(String),
+    /// The config could not be read, is not trusted or does not parse.
+    Config(LoadError),
+    /// A user or their groups could not be looked up.
+    Lookup(String),
+    /// The command was not found or could not be executed, the message names it.
+    Command(io::Error),
+    /// Anything else that stopped the command from running.
+    Failed(String),
+}
+
+impl Error {
+    /// The status execas exits with, scripts can rely on these.
+    ///
+    /// Not found and not executable use the same statuses as the shell, everything stopping the
+    /// command before it is run has a small number of its own.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Denied | Error::Authentication(_) | Error::Failed(_) => 1,
+            Error::Config(LoadError::Io(_)) => 3,
+            Error::Config(LoadError::Parse(..)) => 4,
+            Error::Lookup(_) => 5,
+            Error::Command(err) if err.kind() == io::ErrorKind::NotFound => 127,
+            Error::Command(_) => 126,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Denied => write!(f, "Operation not permitted"),
+            Error::Authentication(message) | Error::Lookup(message) | Error::Failed(message) => {
+                write!(f, "{}", message)
+            }
+            Error::Config(err) => write!(f, "{}", err),
+            Error::Command(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<LoadError> for Error {
+    fn from(err: LoadError) -> Error {
+        Error::Config(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn exit_codes_tell_failures_apart() {
+        let parse = Config::parse("allow alice\n").unwrap_err();
+        let cases = [
+            (Error::Denied, 1),
+            (
+                Error::Authentication("Authentication failed".to_string()),
+                1,
+            ),
+            (
+                Error::Config(LoadError::Io(io::ErrorKind::NotFound.into())),
+                3,
+            ),
+            (
+                Error::Config(LoadError::Parse(PathBuf::from("/etc/execas.conf"), parse)),
+                4,
+            ),
+            (Error::Lookup("unknown user bob".to_string()), 5),
+            (
+                Error::Command(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "nope: command not found",
+                )),
+                127,
+            ),
+            (
+                Error::Command(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "/etc/passwd: Permission denied",
+                )),
+                126,
+            ),
+        ];
+
+        for (err, code) in cases {
+            assert_eq!(err.exit_code(), code, "{}", err);
+        }
+    }
+
+    #[test]
+    fn messages_are_meant_for_users() {
+        assert_eq!(Error::Denied.to_string(), "Operation not permitted");
+
+        let parse = Config::parse("allow alice\n").unwrap_err();
+        let err = Error::Config(LoadError::Parse(PathBuf::from("/etc/execas.conf"), parse));
+        assert_eq!(
+            err.to_string(),
+            "/etc/execas.conf:1:1: expected permit or deny, found \"allow\""
+        );
+    }
+}
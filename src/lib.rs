@@ -0,0 +1,26 @@
+//! Execute commands as another user according to the rules in `/etc/execas.conf`.
+//!
+//! The policy engine is [`config::Config::evaluate`], [`run::Runner`] drives a whole invocation
+//! through the traits in [`run`] so it can be exercised without root, and [`system`] implements
+//! them for the real machine.
+
+pub mod config;
+pub mod diagnose;
+mod edit;
+pub mod env;
+pub mod error;
+pub mod lockout;
+#[cfg(feature = "pam")]
+mod pam;
+pub mod path;
+mod persist;
+pub mod prompt;
+pub mod run;
+pub mod secure;
+#[cfg(not(feature = "pam"))]
+mod shadow;
+pub mod syslog;
+pub mod system;
+#[cfg(test)]
+mod testing;
+pub mod user;
@@ -0,0 +1,236 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use nix::fcntl::{flock, FlockArg};
+use nix::unistd::Uid;
+
+use crate::config::Settings;
+use crate::secure;
+
+/// Root only directory with a failure record per user, it has to survive reboots.
+const STATE_DIR: &str = "/var/lib/execas";
+
+/// The longest delay enforced between two attempts before the lockout kicks in.
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// What the failure record allows right now.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// Authentication may be attempted after waiting this long.
+    Wait(Duration),
+    /// The user is locked out for this much longer.
+    Locked(Duration),
+}
+
+/// The consecutive failed authentications of a user and when the last one happened.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Record {
+    pub count: u32,
+    /// Seconds since the epoch.
+    pub last: Duration,
+}
+
+impl Record {
+    /// Decide whether the user may try to authenticate at `now`.
+    pub fn verdict(&self, settings: &Settings, now: Duration) -> Verdict {
+        if self.count == 0 {
+            return Verdict::Wait(Duration::ZERO);
+        }
+
+        // a clock that went backwards should not shorten the wait
+        let since = now.checked_sub(self.last).unwrap_or(Duration::ZERO);
+
+        if self.count >= settings.max_failures {
+            return match settings.lockout.checked_sub(since) {
+                Some(left) if !left.is_zero() => Verdict::Locked(left),
+                _ => Verdict::Wait(Duration::ZERO),
+            };
+        }
+
+        // 1, 2, 4, 8 ... seconds after each consecutive failure
+        let delay = Duration::from_secs(1 << (self.count - 1).min(16)).min(MAX_DELAY);
+        Verdict::Wait(delay.checked_sub(since).unwrap_or(Duration::ZERO))
+    }
+
+    /// Count a failure at `now`, a failure after an expired lockout starts counting again.
+    pub fn failed(&mut self, settings: &Settings, now: Duration) {
+        let expired = self.count >= settings.max_failures
+            && self.verdict(settings, now) == Verdict::Wait(Duration::ZERO);
+        self.count = if expired {
+            1
+        } else {
+            self.count.saturating_add(1)
+        };
+        self.last = now;
+    }
+}
+
+/// The failure record of a user as stored on disk.
+///
+/// The file is locked for as long as this is alive so concurrent attempts by the same user are
+/// handled one at a time and can not get around the delay.
+pub struct Failures {
+    file: File,
+    pub record: Record,
+}
+
+impl Failures {
+    /// Open and lock the record for `uid`, waiting for any other attempt by them to finish.
+    pub fn lock(uid: Uid) -> io::Result<Failures> {
+        secure::ensure_private_dir(Path::new(STATE_DIR))?;
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .mode(0o600)
+            .custom_flags(libc::O_NOFOLLOW | libc::O_CLOEXEC)
+            .open(record_path(uid))?;
+        flock(file.as_raw_fd(), FlockArg::LockExclusive)?;
+
+        let metadata = file.metadata()?;
+        if !metadata.is_file() || metadata.uid() != 0 || metadata.mode() & 0o077 != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!(
+                    "{} is not a file only root can access",
+                    record_path(uid).display()
+                ),
+            ));
+        }
+
+        let mut text = String::new();
+        file.read_to_string(&mut text)?;
+
+        // an empty or unreadable record counts as no failures
+        let mut fields = text.split_whitespace().map(str::parse::<u64>);
+        let record = match (fields.next(), fields.next()) {
+            (Some(Ok(count)), Some(Ok(last))) => Record {
+                count: count.min(u32::MAX as u64) as u32,
+                last: Duration::from_secs(last),
+            },
+            _ => Record::default(),
+        };
+
+        Ok(Failures { file, record })
+    }
+
+    /// Write the record back, the lock is held until this is dropped.
+    pub fn save(&mut self) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.set_len(0)?;
+        writeln!(
+            self.file,
+            "{} {}",
+            self.record.count,
+            self.record.last.as_secs()
+        )
+    }
+}
+
+/// Remove the failure record of `uid`, lifting any lockout.
+pub fn reset(uid: Uid) -> io::Result<()> {
+    secure::check_private_dir(Path::new(STATE_DIR))?;
+
+    match fs::remove_file(record_path(uid)) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+fn record_path(uid: Uid) -> PathBuf {
+    Path::new(STATE_DIR).join(format!("failures-{}", uid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START: Duration = Duration::from_secs(1_000_000);
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    fn settings() -> Settings {
+        Settings {
+            max_failures: 3,
+            lockout: secs(60),
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn delay_doubles_after_each_failure() {
+        let settings = settings();
+        let mut record = Record::default();
+        assert_eq!(record.verdict(&settings, START), Verdict::Wait(secs(0)));
+
+        record.failed(&settings, START);
+        assert_eq!(record.verdict(&settings, START), Verdict::Wait(secs(1)));
+
+        record.failed(&settings, START);
+        assert_eq!(record.verdict(&settings, START), Verdict::Wait(secs(2)));
+        // time already waited counts
+        assert_eq!(
+            record.verdict(&settings, START + secs(1)),
+            Verdict::Wait(secs(1))
+        );
+        assert_eq!(
+            record.verdict(&settings, START + secs(5)),
+            Verdict::Wait(secs(0))
+        );
+    }
+
+    #[test]
+    fn delay_is_capped() {
+        let settings = Settings {
+            max_failures: 100,
+            ..settings()
+        };
+        let record = Record {
+            count: 50,
+            last: START,
+        };
+        assert_eq!(record.verdict(&settings, START), Verdict::Wait(MAX_DELAY));
+    }
+
+    #[test]
+    fn locks_out_after_max_failures() {
+        let settings = settings();
+        let mut record = Record::default();
+        for _ in 0..3 {
+            record.failed(&settings, START);
+        }
+
+        assert_eq!(
+            record.verdict(&settings, START + secs(10)),
+            Verdict::Locked(secs(50))
+        );
+        assert_eq!(
+            record.verdict(&settings, START + secs(60)),
+            Verdict::Wait(secs(0))
+        );
+
+        // the count starts over once the lockout has expired
+        record.failed(&settings, START + secs(60));
+        assert_eq!(record.count, 1);
+    }
+
+    #[test]
+    fn clock_going_backwards_does_not_shorten_the_wait() {
+        let settings = settings();
+        let record = Record {
+            count: 3,
+            last: START,
+        };
+        assert_eq!(
+            record.verdict(&settings, START - secs(100)),
+            Verdict::Locked(secs(60))
+        );
+    }
+}
@@ -1,57 +1,322 @@
-use std::ffi::CString;
-use std::fs::File;
-use std::io::{self, Read, Write};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process;
 
-use nix::unistd::execvp;
-use nix::unistd::User;
+use clap::{value_parser, Arg, ArgAction, Command};
 use nix::unistd::{geteuid, getuid};
 
+use execas::config::{Config, Decision, Request, Settings, CONFIG_PATH};
+use execas::error::Error;
+use execas::prompt::Prompter;
+use execas::run::{self, Executor, Invocation, Mode, Runner, UserDb};
+use execas::syslog::Syslog;
+use execas::system::{SystemAuthenticator, SystemClock, SystemExecutor, SystemUsers};
+use execas::{diagnose, lockout, secure, user};
+
+/// The runner wired up to the real machine.
+type System = Runner<SystemUsers, SystemAuthenticator<SystemClock>, SystemExecutor, Syslog>;
+
+fn cli() -> Command<'static> {
+    Command::new("execas")
+        .about("Execute a command as another user")
+        .trailing_var_arg(true)
+        .arg(
+            Arg::new("user")
+                .short('u')
+                .help("Run the command as this user")
+                .takes_value(true)
+                .value_name("user")
+                .default_value("root"),
+        )
+        .arg(
+            Arg::new("check")
+                .short('C')
+                .help("Check the syntax of a config file and optionally whether it allows the command")
+                .takes_value(true)
+                .value_name("file")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("shell")
+                .short('s')
+                .help("Run the target user's shell")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(&["login", "command"]),
+        )
+        .arg(
+            Arg::new("login")
+                .short('i')
+                .help("Run the target user's shell as a login shell in their home directory")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("command"),
+        )
+        .arg(
+            Arg::new("edit")
+                .short('e')
+                .help("Edit the files as the target user with your own editor")
+                .action(ArgAction::SetTrue)
+                .requires("command")
+                .conflicts_with_all(&["shell", "login"]),
+        )
+        .arg(
+            Arg::new("non-interactive")
+                .short('n')
+                .help("Fail instead of asking for a password")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("askpass")
+                .short('A')
+                .help("Ask for the password with the program in EXECAS_ASKPASS instead of the terminal")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("non-interactive"),
+        )
+        .arg(
+            Arg::new("list")
+                .short('l')
+                .help("List the rules that apply to you, or whether you may run the command")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(&["check", "shell", "login"]),
+        )
+        .arg(
+            Arg::new("reset-failures")
+                .long("reset-failures")
+                .help("Forget the failed authentications of a user, lifting their lockout")
+                .takes_value(true)
+                .value_name("user")
+                .conflicts_with_all(&["check", "shell", "login", "edit", "list", "command"]),
+        )
+        .arg(
+            Arg::new("command")
+                .help("The command to run followed by its arguments")
+                .required_unless_present_any(["check", "shell", "login", "list", "reset-failures"])
+                .multiple_values(true)
+                .value_parser(value_parser!(OsString)),
+        )
+}
+
 fn main() {
-    // get the command
-    let args = ["whoami"].map(|s| CString::new(s).unwrap());
+    let matches = cli().get_matches();
+
+    let shell = *matches
+        .get_one::<bool>("shell")
+        .expect("flags default to false");
+    let login = *matches
+        .get_one::<bool>("login")
+        .expect("flags default to false");
+    let edit = *matches
+        .get_one::<bool>("edit")
+        .expect("flags default to false");
+    let list = *matches
+        .get_one::<bool>("list")
+        .expect("flags default to false");
+    let non_interactive = *matches
+        .get_one::<bool>("non-interactive")
+        .expect("flags default to false");
+    let askpass = *matches
+        .get_one::<bool>("askpass")
+        .expect("flags default to false");
+
+    // get the command, every argument after it is passed through untouched
+    let command: Vec<OsString> = matches
+        .get_many::<OsString>("command")
+        .map(|values| values.cloned().collect())
+        .unwrap_or_default();
+    let target_name = matches
+        .get_one::<String>("user")
+        .expect("user has a default");
+
+    if let Some(path) = matches.get_one::<PathBuf>("check") {
+        check_config(path, target_name, &command, edit);
+    }
 
     // check to make sure we are root (effective user id). if not we can try to run some diagnostics
-    let euid = geteuid();
-    let ruid = getuid();
+    if !geteuid().is_root() {
+        // explain what is wrong with the installation, the first problem decides the exit status
+        let (exe, problems) = diagnose::diagnose();
+        eprintln!("execas: not running as root");
+        for problem in &problems {
+            eprintln!("execas: {}", problem.describe(&exe));
+        }
+        process::exit(problems[0].exit_code());
+    }
 
-    if !euid.is_root() {
-        // run diagnostics and exit appropriately
-        eprintln!("You are not root! :(");
-        return;
+    if let Some(name) = matches.get_one::<String>("reset-failures") {
+        reset_failures(name);
     }
 
-    // force the user to reauthenticate
-    let real_user = User::from_uid(ruid)
-        .expect("Failed to get username from ruid")
-        .expect("No username for that uid");
+    // the settings are only known once the config is loaded
+    let mut runner = Runner {
+        users: SystemUsers,
+        auth: SystemAuthenticator {
+            clock: SystemClock,
+            settings: Settings::default(),
+            prompter: Prompter::Tty,
+        },
+        executor: SystemExecutor,
+        audit: Syslog,
+    };
 
-    let mut given_password = String::new();
-    print!("password: ");
-    io::stdout().flush().unwrap();
-    io::stdin()
-        .read_line(&mut given_password)
-        .expect("Failed to read password from stdin");
+    let mode = if shell {
+        Mode::Shell
+    } else if login {
+        Mode::Login
+    } else if edit {
+        Mode::Edit(command)
+    } else {
+        Mode::Command(command)
+    };
 
-    // TODO: compare given_password and real_user.password and ensure they match
+    let mut invocation = runner
+        .invocation(getuid(), target_name, mode, std::env::vars_os().collect())
+        .unwrap_or_else(|err| fail(err));
+    invocation.non_interactive = non_interactive;
 
     // check the conf file to make they are suppose to be able to run the command
-    let mut conf_f = File::open("./execas.conf").expect("Could not read execas conf file");
-    let mut conf_str = String::new();
-    conf_f
-        .read_to_string(&mut conf_str)
-        .expect("Failed to read conf file");
+    let config = Config::load(Path::new(CONFIG_PATH))
+        .unwrap_or_else(|err| fail(runner.refuse(&invocation, err.into())));
+    runner.auth.settings = config.settings.clone();
 
-    let allowed_users: Vec<&str> = conf_str
-        .split('\n')
-        .map(|user_txt| user_txt.trim())
-        .collect();
+    if askpass {
+        runner.auth.prompter = Prompter::Askpass {
+            program: askpass_program().unwrap_or_else(|err| fail(err)),
+            user: invocation.user.clone(),
+        };
+    }
 
-    if !allowed_users.contains(&real_user.name.as_str()) {
-        eprintln!("user {} is not found in execas.conf", real_user.name);
-        return;
+    if list {
+        list_rules(&mut runner, &config, &invocation);
     }
 
-    // exec the command the user is trying to run
-    let command = &args[0];
-    execvp(command, &args).unwrap();
+    match runner.run(&config, &invocation) {
+        Ok(code) => process::exit(code),
+        Err(err) => fail(err),
+    }
+}
+
+/// Report `err` and exit with its status.
+fn fail(err: Error) -> ! {
+    eprintln!("execas: {}", err);
+    process::exit(err.exit_code());
+}
+
+/// The askpass program named by `EXECAS_ASKPASS`, which only root may have been able to change.
+fn askpass_program() -> Result<PathBuf, Error> {
+    let program = std::env::var_os("EXECAS_ASKPASS").ok_or_else(|| {
+        Error::Failed("-A needs EXECAS_ASKPASS set to an askpass program".to_string())
+    })?;
+
+    secure::trusted_program(Path::new(&program))
+        .map_err(|err| Error::Failed(format!("EXECAS_ASKPASS: {}", err)))
+}
+
+/// Parse `path` and what it includes with the privileges of the caller and report what it decides for `command`, or for
+/// editing the files in it with `edit`.
+fn check_config(path: &Path, target_name: &str, command: &[OsString], edit: bool) -> ! {
+    // the file could be anything so never read it as root
+    if let Err(err) = user::drop_privileges() {
+        fail(Error::Failed(format!("failed to drop privileges: {}", err)));
+    }
+
+    // the files it includes are read the same way
+    let config = Config::load_unchecked(path).unwrap_or_else(|err| fail(err.into()));
+
+    if command.is_empty() {
+        process::exit(0);
+    }
+
+    let real_user = match SystemUsers.by_uid(getuid()) {
+        Ok(Some(user)) => user,
+        _ => fail(Error::Lookup(format!("unknown user id {}", getuid()))),
+    };
+
+    let caller = SystemUsers.caller(&real_user).unwrap_or_else(|err| {
+        fail(Error::Lookup(format!(
+            "failed to look up the groups of {}: {}",
+            real_user.name, err
+        )))
+    });
+
+    // the target does not have to exist on this machine, the file may be meant for another one
+    let search_path = OsString::from(&config.settings.secure_path);
+    let mut resolved = command.to_vec();
+    if edit {
+        for file in &mut resolved {
+            *file = run::edit_path(file)
+                .unwrap_or_else(|err| fail(err))
+                .into_os_string();
+        }
+    } else if let Ok(path) = SystemExecutor.resolve(&command[0], &search_path) {
+        resolved[0] = path.into_os_string();
+    }
+
+    report(config.evaluate(
+        &Request {
+            caller,
+            target: target_name.to_string(),
+            command: resolved,
+            edit,
+            hosts: SystemUsers.host_names(),
+        },
+        |command, search_path| SystemExecutor.resolve(command, search_path),
+    ));
+}
+
+/// Print the rules applying to the caller, or what they decide for the command when one is given.
+fn list_rules(runner: &mut System, config: &Config, invocation: &Invocation) -> ! {
+    let listing = runner
+        .list(config, invocation)
+        .unwrap_or_else(|err| fail(err));
+    if let Some(decision) = listing.decision {
+        report(decision);
+    }
+
+    for rule in listing.rules {
+        println!("{}", rule);
+    }
+    process::exit(0);
+}
+
+/// Lift the lockout of `name`, only the real root user may do this.
+fn reset_failures(name: &str) -> ! {
+    if !getuid().is_root() {
+        fail(Error::Failed(
+            "only root can reset failed authentications".to_string(),
+        ));
+    }
+
+    let user = match SystemUsers.by_name(name) {
+        Ok(Some(user)) => user,
+        Ok(None) => fail(Error::Lookup(format!("unknown user {}", name))),
+        Err(err) => fail(Error::Lookup(format!(
+            "failed to look up user {}: {}",
+            name, err
+        ))),
+    };
+
+    if let Err(err) = lockout::reset(user.uid) {
+        fail(Error::Failed(format!(
+            "failed to reset the failures of {}: {}",
+            name, err
+        )));
+    }
+    process::exit(0);
+}
+
+/// Print the decision the config makes, exiting successfully only if it permits.
+fn report(decision: Decision) -> ! {
+    match decision {
+        Decision::Permit(rule) => {
+            if rule.options.nopass {
+                println!("permit nopass");
+            } else {
+                println!("permit");
+            }
+            process::exit(0);
+        }
+        Decision::Deny(_) => {
+            println!("deny");
+            process::exit(1);
+        }
+    }
 }
@@ -0,0 +1,432 @@
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::fmt;
+use std::ptr;
+
+use nix::sys::signal::{signal, SigHandler, Signal};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{fork, ForkResult};
+
+use crate::prompt::Prompter;
+
+// the name of the service file in /etc/pam.d
+const SERVICE: &str = "execas";
+
+const PAM_SUCCESS: c_int = 0;
+const PAM_SYSTEM_ERR: c_int = 4;
+const PAM_BUF_ERR: c_int = 5;
+const PAM_AUTH_ERR: c_int = 7;
+const PAM_USER_UNKNOWN: c_int = 10;
+const PAM_MAXTRIES: c_int = 11;
+const PAM_NEW_AUTHTOK_REQD: c_int = 12;
+const PAM_CONV_ERR: c_int = 19;
+
+const PAM_USER: c_int = 2;
+const PAM_TTY: c_int = 3;
+const PAM_RUSER: c_int = 8;
+
+const PAM_PROMPT_ECHO_OFF: c_int = 1;
+const PAM_PROMPT_ECHO_ON: c_int = 2;
+const PAM_ERROR_MSG: c_int = 3;
+const PAM_TEXT_INFO: c_int = 4;
+
+const PAM_ESTABLISH_CRED: c_int = 0x0002;
+const PAM_DELETE_CRED: c_int = 0x0004;
+const PAM_CHANGE_EXPIRED_AUTHTOK: c_int = 0x0020;
+
+#[repr(C)]
+struct PamMessage {
+    msg_style: c_int,
+    msg: *const c_char,
+}
+
+#[repr(C)]
+struct PamResponse {
+    resp: *mut c_char,
+    resp_retcode: c_int,
+}
+
+type ConvFn =
+    extern "C" fn(c_int, *mut *const PamMessage, *mut *mut PamResponse, *mut c_void) -> c_int;
+
+#[repr(C)]
+struct PamConv {
+    conv: ConvFn,
+    appdata_ptr: *mut c_void,
+}
+
+#[repr(C)]
+struct PamHandle {
+    _private: [u8; 0],
+}
+
+#[link(name = "pam")]
+extern "C" {
+    fn pam_start(
+        service_name: *const c_char,
+        user: *const c_char,
+        pam_conversation: *const PamConv,
+        pamh: *mut *mut PamHandle,
+    ) -> c_int;
+    fn pam_end(pamh: *mut PamHandle, pam_status: c_int) -> c_int;
+    fn pam_set_item(pamh: *mut PamHandle, item_type: c_int, item: *const c_void) -> c_int;
+    fn pam_authenticate(pamh: *mut PamHandle, flags: c_int) -> c_int;
+    fn pam_acct_mgmt(pamh: *mut PamHandle, flags: c_int) -> c_int;
+    fn pam_chauthtok(pamh: *mut PamHandle, flags: c_int) -> c_int;
+    fn pam_setcred(pamh: *mut PamHandle, flags: c_int) -> c_int;
+    fn pam_open_session(pamh: *mut PamHandle, flags: c_int) -> c_int;
+    fn pam_close_session(pamh: *mut PamHandle, flags: c_int) -> c_int;
+    fn pam_strerror(pamh: *mut PamHandle, errnum: c_int) -> *const c_char;
+}
+
+#[derive(Debug)]
+pub struct PamError {
+    step: &'static str,
+    status: c_int,
+    message: String,
+}
+
+impl PamError {
+    /// Whether the auth stack checked the credential and turned it down, rather than failing to
+    /// ask for or check it.
+    pub fn rejected(&self) -> bool {
+        self.step == "pam_authenticate"
+            && matches!(self.status, PAM_AUTH_ERR | PAM_USER_UNKNOWN | PAM_MAXTRIES)
+    }
+}
+
+impl fmt::Display for PamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.step, self.message)
+    }
+}
+
+/// An authenticated PAM transaction for the invoking user.
+pub struct Pam {
+    handle: *mut PamHandle,
+    status: c_int,
+    cred: bool,
+    session: bool,
+    /// Handed to the conversation function, boxed so it does not move while pam holds a pointer.
+    _prompter: Box<Prompter>,
+}
+
+impl Pam {
+    /// Start a transaction with the `execas` service for `user`, asking questions with `prompter`.
+    pub fn start(user: &str, tty: Option<&str>, prompter: Prompter) -> Result<Pam, PamError> {
+        let service = CString::new(SERVICE).expect("service name has no nul bytes");
+        Pam::begin(user, tty, prompter, |user, conv, handle| unsafe {
+            pam_start(service.as_ptr(), user, conv, handle)
+        })
+    }
+
+    /// Start a transaction with `start`, which is given the user, conversation and handle to fill
+    /// in for pam_start.
+    fn begin<F>(
+        user: &str,
+        tty: Option<&str>,
+        prompter: Prompter,
+        start: F,
+    ) -> Result<Pam, PamError>
+    where
+        F: FnOnce(*const c_char, *const PamConv, *mut *mut PamHandle) -> c_int,
+    {
+        let user = cstring("pam_start", user)?;
+        let prompter = Box::new(prompter);
+        let conv = PamConv {
+            conv: conversation,
+            appdata_ptr: &*prompter as *const Prompter as *mut c_void,
+        };
+
+        // pam_start copies the conversation struct so it does not need to outlive the call
+        let mut handle = ptr::null_mut();
+        let status = start(user.as_ptr(), &conv, &mut handle);
+        if status != PAM_SUCCESS || handle.is_null() {
+            return Err(PamError {
+                step: "pam_start",
+                status,
+                message: format!("failed with status {}", status),
+            });
+        }
+
+        let mut pam = Pam {
+            handle,
+            status,
+            cred: false,
+            session: false,
+            _prompter: prompter,
+        };
+
+        pam.set_item(PAM_RUSER, &user)?;
+        if let Some(tty) = tty {
+            pam.set_item(PAM_TTY, &cstring("pam_set_item", tty)?)?;
+        }
+
+        Ok(pam)
+    }
+
+    /// Run the auth stack when `prompt` is set and then the account stack.
+    pub fn authenticate(&mut self, prompt: bool) -> Result<(), PamError> {
+        if prompt {
+            self.check("pam_authenticate", |h| unsafe { pam_authenticate(h, 0) })?;
+        }
+
+        let status = unsafe { pam_acct_mgmt(self.handle, 0) };
+        if status == PAM_NEW_AUTHTOK_REQD {
+            self.check("pam_chauthtok", |h| unsafe {
+                pam_chauthtok(h, PAM_CHANGE_EXPIRED_AUTHTOK)
+            })
+        } else {
+            self.check("pam_acct_mgmt", |_| status)
+        }
+    }
+
+    /// Establish credentials and open a session for `target` then run `child` in a forked process.
+    ///
+    /// The session stays open until the child exits, its wait status is returned.
+    pub fn run<F: FnOnce()>(&mut self, target: &str, child: F) -> Result<WaitStatus, PamError> {
+        self.set_item(PAM_USER, &cstring("pam_set_item", target)?)?;
+
+        self.check("pam_setcred", |h| unsafe {
+            pam_setcred(h, PAM_ESTABLISH_CRED)
+        })?;
+        self.cred = true;
+
+        self.check("pam_open_session", |h| unsafe { pam_open_session(h, 0) })?;
+        self.session = true;
+
+        match unsafe { fork() } {
+            Ok(ForkResult::Child) => {
+                child();
+                std::process::exit(1);
+            }
+            Ok(ForkResult::Parent { child }) => {
+                // the terminal sends these to the whole process group, we need to stay alive to close the session
+                unsafe {
+                    let _ = signal(Signal::SIGINT, SigHandler::SigIgn);
+                    let _ = signal(Signal::SIGQUIT, SigHandler::SigIgn);
+                }
+
+                loop {
+                    match waitpid(child, None) {
+                        Ok(status @ (WaitStatus::Exited(..) | WaitStatus::Signaled(..))) => {
+                            return Ok(status)
+                        }
+                        Ok(_) | Err(nix::errno::Errno::EINTR) => continue,
+                        Err(err) => {
+                            return Err(PamError {
+                                step: "waitpid",
+                                status: PAM_SYSTEM_ERR,
+                                message: err.to_string(),
+                            })
+                        }
+                    }
+                }
+            }
+            Err(err) => Err(PamError {
+                step: "fork",
+                status: PAM_SYSTEM_ERR,
+                message: err.to_string(),
+            }),
+        }
+    }
+
+    fn set_item(&mut self, item_type: c_int, value: &CStr) -> Result<(), PamError> {
+        // pam copies string items so the CStr only has to live for the call
+        self.check("pam_set_item", |h| unsafe {
+            pam_set_item(h, item_type, value.as_ptr() as *const c_void)
+        })
+    }
+
+    fn check<F: FnOnce(*mut PamHandle) -> c_int>(
+        &mut self,
+        step: &'static str,
+        call: F,
+    ) -> Result<(), PamError> {
+        self.status = call(self.handle);
+        if self.status == PAM_SUCCESS {
+            return Ok(());
+        }
+
+        let message = unsafe {
+            let msg = pam_strerror(self.handle, self.status);
+            if msg.is_null() {
+                format!("failed with status {}", self.status)
+            } else {
+                CStr::from_ptr(msg).to_string_lossy().into_owned()
+            }
+        };
+
+        Err(PamError {
+            step,
+            status: self.status,
+            message,
+        })
+    }
+}
+
+impl Drop for Pam {
+    fn drop(&mut self) {
+        unsafe {
+            if self.session {
+                pam_close_session(self.handle, 0);
+            }
+            if self.cred {
+                pam_setcred(self.handle, PAM_DELETE_CRED);
+            }
+            pam_end(self.handle, self.status);
+        }
+    }
+}
+
+fn cstring(step: &'static str, value: &str) -> Result<CString, PamError> {
+    CString::new(value).map_err(|_| PamError {
+        step,
+        status: PAM_SYSTEM_ERR,
+        message: "value contains a nul byte".to_string(),
+    })
+}
+
+extern "C" fn conversation(
+    num_msg: c_int,
+    msg: *mut *const PamMessage,
+    resp: *mut *mut PamResponse,
+    appdata_ptr: *mut c_void,
+) -> c_int {
+    if num_msg <= 0 || msg.is_null() || resp.is_null() || appdata_ptr.is_null() {
+        return PAM_CONV_ERR;
+    }
+    let prompter = unsafe { &*(appdata_ptr as *const Prompter) };
+    let count = num_msg as usize;
+
+    // the responses are freed by pam so they have to come from the c allocator
+    let responses =
+        unsafe { libc::calloc(count, std::mem::size_of::<PamResponse>()) } as *mut PamResponse;
+    if responses.is_null() {
+        return PAM_BUF_ERR;
+    }
+
+    for i in 0..count {
+        let (style, text) = unsafe {
+            let message = &**msg.add(i);
+            let text = if message.msg.is_null() {
+                String::new()
+            } else {
+                CStr::from_ptr(message.msg).to_string_lossy().into_owned()
+            };
+            (message.msg_style, text)
+        };
+
+        let answer = match style {
+            PAM_PROMPT_ECHO_OFF => prompter.read_password(&text).ok(),
+            PAM_PROMPT_ECHO_ON => prompter.read_line(&text).ok(),
+            PAM_ERROR_MSG | PAM_TEXT_INFO => {
+                eprintln!("{}", text);
+                continue;
+            }
+            _ => None,
+        };
+
+        let answer = match answer.and_then(|a| CString::new(a).ok()) {
+            Some(answer) => answer,
+            None => {
+                free_responses(responses, count);
+                return PAM_CONV_ERR;
+            }
+        };
+
+        unsafe {
+            (*responses.add(i)).resp = libc::strdup(answer.as_ptr());
+        }
+    }
+
+    unsafe {
+        *resp = responses;
+    }
+    PAM_SUCCESS
+}
+
+fn free_responses(responses: *mut PamResponse, count: usize) {
+    unsafe {
+        for i in 0..count {
+            let resp = (*responses.add(i)).resp;
+            if !resp.is_null() {
+                libc::free(resp as *mut c_void);
+            }
+        }
+        libc::free(responses as *mut c_void);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[link(name = "pam")]
+    extern "C" {
+        fn pam_start_confdir(
+            service_name: *const c_char,
+            user: *const c_char,
+            pam_conversation: *const PamConv,
+            confdir: *const c_char,
+            pamh: *mut *mut PamHandle,
+        ) -> c_int;
+    }
+
+    /// Service files built from the modules every Linux-PAM install has.
+    fn services() -> TempDir {
+        let dir = TempDir::new("pam");
+        for (name, auth, account) in [
+            ("permit", "pam_permit.so", "pam_permit.so"),
+            ("deny", "pam_deny.so", "pam_permit.so"),
+            ("expired", "pam_permit.so", "pam_deny.so"),
+        ] {
+            let service = format!("auth required {}\naccount required {}\n", auth, account);
+            fs::write(dir.join(name), service).unwrap();
+        }
+        dir
+    }
+
+    /// Start a transaction with the service `name` from `dir` rather than /etc/pam.d.
+    fn start_in(dir: &Path, name: &str) -> Pam {
+        let service = CString::new(name).unwrap();
+        let confdir = CString::new(dir.to_str().unwrap()).unwrap();
+        Pam::begin(
+            "root",
+            Some("/dev/pts/0"),
+            Prompter::Tty,
+            |user, conv, handle| unsafe {
+                pam_start_confdir(service.as_ptr(), user, conv, confdir.as_ptr(), handle)
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn authenticates_when_the_stack_permits() {
+        let dir = services();
+        start_in(&dir, "permit").authenticate(true).unwrap();
+    }
+
+    #[test]
+    fn denied_credentials_are_rejected() {
+        let dir = services();
+        let err = start_in(&dir, "deny").authenticate(true).unwrap_err();
+        assert_eq!(err.step, "pam_authenticate");
+        assert!(err.rejected());
+
+        // without a prompt only the account stack runs
+        start_in(&dir, "deny").authenticate(false).unwrap();
+    }
+
+    #[test]
+    fn account_failures_are_not_rejected_credentials() {
+        let dir = services();
+        let err = start_in(&dir, "expired").authenticate(true).unwrap_err();
+        assert_eq!(err.step, "pam_acct_mgmt");
+        assert!(!err.rejected());
+    }
+}
@@ -0,0 +1,143 @@
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// Turn the command the caller typed into the absolute path of the file that would be executed.
+///
+/// Names without a slash are looked up in `search_path` like execvp does, anything else is taken
+/// relative to the working directory. Like the shell a file that exists but can not be executed is
+/// a permission error rather than not found.
+pub fn resolve(command: &OsStr, search_path: &OsStr) -> io::Result<PathBuf> {
+    if command.as_bytes().contains(&b'/') {
+        let path = env::current_dir()?.join(command);
+        if is_executable(&path) {
+            return Ok(normalize(&path));
+        }
+        if path.exists() {
+            return Err(not_executable(&path));
+        }
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{}: command not found", path.display()),
+        ));
+    }
+
+    let mut found = None;
+    for dir in env::split_paths(search_path) {
+        // an empty entry means the working directory which is never searched
+        if !dir.is_absolute() {
+            continue;
+        }
+
+        let candidate = dir.join(command);
+        if is_executable(&candidate) {
+            return Ok(normalize(&candidate));
+        }
+        if found.is_none() && candidate.is_file() {
+            found = Some(candidate);
+        }
+    }
+
+    match found {
+        Some(path) => Err(not_executable(&path)),
+        None => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{}: command not found", command.to_string_lossy()),
+        )),
+    }
+}
+
+/// Resolve symlinks and `..` in the directories of `path` but not the file itself.
+///
+/// Multi-call binaries behave differently depending on the name they are run as, so the last
+/// component is kept as is. Paths whose directory does not exist are returned unchanged.
+pub fn normalize(path: &Path) -> PathBuf {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => fs::canonicalize(parent)
+            .map(|parent| parent.join(name))
+            .unwrap_or_else(|_| path.to_path_buf()),
+        _ => path.to_path_buf(),
+    }
+}
+
+fn not_executable(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!("{}: Permission denied", path.display()),
+    )
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path)
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+    use crate::testing::TempDir;
+
+    /// A fresh directory holding an executable `tool` and a plain file `data`.
+    fn fixture(name: &str) -> TempDir {
+        let dir = TempDir::new(&format!("path-{}", name));
+
+        File::create(dir.join("tool")).unwrap();
+        fs::set_permissions(dir.join("tool"), fs::Permissions::from_mode(0o755)).unwrap();
+        File::create(dir.join("data")).unwrap();
+        fs::set_permissions(dir.join("data"), fs::Permissions::from_mode(0o644)).unwrap();
+
+        dir
+    }
+
+    #[test]
+    fn searches_only_absolute_entries() {
+        let dir = fixture("search");
+        let search_path = env::join_paths(["relative", dir.to_str().unwrap()]).unwrap();
+
+        assert_eq!(
+            resolve(OsStr::new("tool"), &search_path).unwrap(),
+            dir.join("tool")
+        );
+        assert_eq!(
+            resolve(OsStr::new("missing"), &search_path)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn files_that_can_not_run_are_permission_errors() {
+        let dir = fixture("exec");
+
+        let err = resolve(OsStr::new("data"), dir.as_os_str()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+
+        let err = resolve(dir.join("data").as_os_str(), OsStr::new("")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+
+        let err = resolve(dir.join("nope").as_os_str(), OsStr::new("")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn normalize_keeps_the_file_name() {
+        let dir = fixture("normalize");
+        let link = dir.join("link");
+        std::os::unix::fs::symlink(dir.join("tool"), &link).unwrap();
+
+        let through = dir
+            .join(".")
+            .join("..")
+            .join(dir.file_name().unwrap())
+            .join("link");
+        assert_eq!(normalize(&through), link);
+    }
+}
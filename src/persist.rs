@@ -0,0 +1,177 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use nix::sys::termios::tcgetsid;
+use nix::time::{clock_gettime, ClockId};
+use nix::unistd::{getsid, Uid};
+
+use crate::secure;
+
+/// Root only directory holding the timestamp records, it lives on a tmpfs so reboots clear it too.
+const TIMESTAMP_DIR: &str = "/run/execas";
+const BOOT_ID_PATH: &str = "/proc/sys/kernel/random/boot_id";
+
+/// How long an authentication stays valid for `persist` rules without an explicit timeout.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// The values identifying a login session, a record is only honoured if all of them still match.
+#[derive(Debug, PartialEq, Eq)]
+struct Session {
+    boot_id: String,
+    tty: u64,
+    leader: i32,
+    leader_start: u64,
+}
+
+/// The persisted authentication record for a user on the current session.
+pub struct Timestamp {
+    path: PathBuf,
+    session: Session,
+}
+
+impl Timestamp {
+    /// Locate the record for `uid` on the current session.
+    ///
+    /// Fails when there is no controlling terminal or the terminal belongs to another session.
+    pub fn current(uid: Uid) -> io::Result<Timestamp> {
+        let tty = proc_stat_field("self", 7)?;
+        if tty == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "no controlling terminal",
+            ));
+        }
+
+        // a terminal reopened by another login has a different session attached to it
+        let leader = getsid(None)?;
+        let tty_file = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NOCTTY | libc::O_CLOEXEC)
+            .open("/dev/tty")?;
+        if tcgetsid(tty_file.as_raw_fd())? != leader {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "terminal belongs to another session",
+            ));
+        }
+
+        let session = Session {
+            boot_id: fs::read_to_string(BOOT_ID_PATH)?.trim().to_string(),
+            tty,
+            leader: leader.as_raw(),
+            leader_start: proc_stat_field(&leader.to_string(), 22)?,
+        };
+
+        let path =
+            Path::new(TIMESTAMP_DIR).join(format!("{}-{}-{}", uid, session.tty, session.leader));
+
+        Ok(Timestamp { path, session })
+    }
+
+    /// Whether the user authenticated on this session less than `timeout` ago.
+    pub fn is_valid(&self, timeout: Duration) -> bool {
+        let authenticated = match self.read() {
+            Ok(Some(authenticated)) => authenticated,
+            _ => return false,
+        };
+
+        match now() {
+            Ok(now) => now >= authenticated && now - authenticated < timeout,
+            Err(_) => false,
+        }
+    }
+
+    /// Record a successful authentication now.
+    pub fn update(&self) -> io::Result<()> {
+        secure::ensure_private_dir(Path::new(TIMESTAMP_DIR))?;
+
+        let record = format!(
+            "{} {} {} {} {}\n",
+            self.session.boot_id,
+            self.session.tty,
+            self.session.leader,
+            self.session.leader_start,
+            now()?.as_secs()
+        );
+
+        // write a fresh file and move it into place so a reader never sees half a record
+        let tmp = self.path.with_extension("tmp");
+        let _ = fs::remove_file(&tmp);
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .custom_flags(libc::O_NOFOLLOW | libc::O_CLOEXEC)
+            .open(&tmp)?;
+        file.write_all(record.as_bytes())?;
+        fs::rename(&tmp, &self.path)
+    }
+
+    /// Read back when the record was written, `None` if it belongs to a different session.
+    fn read(&self) -> io::Result<Option<Duration>> {
+        secure::check_private_dir(Path::new(TIMESTAMP_DIR))?;
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NOFOLLOW | libc::O_CLOEXEC)
+            .open(&self.path)?;
+        let metadata = file.metadata()?;
+        if !metadata.is_file() || metadata.uid() != 0 || metadata.mode() & 0o077 != 0 {
+            return Ok(None);
+        }
+
+        let mut record = String::new();
+        file.read_to_string(&mut record)?;
+
+        let fields: Vec<&str> = record.split_whitespace().collect();
+        let (session, authenticated) = match fields[..] {
+            [boot_id, tty, leader, leader_start, authenticated] => {
+                let session = Session {
+                    boot_id: boot_id.to_string(),
+                    tty: tty.parse().map_err(invalid)?,
+                    leader: leader.parse().map_err(invalid)?,
+                    leader_start: leader_start.parse().map_err(invalid)?,
+                };
+                (session, authenticated.parse().map_err(invalid)?)
+            }
+            _ => return Ok(None),
+        };
+
+        if session != self.session {
+            return Ok(None);
+        }
+
+        Ok(Some(Duration::from_secs(authenticated)))
+    }
+}
+
+/// Time since boot, unaffected by changes to the wall clock.
+fn now() -> io::Result<Duration> {
+    Ok(clock_gettime(ClockId::CLOCK_BOOTTIME)?.into())
+}
+
+/// Read a numeric field, counting from 1 like proc(5), out of /proc/<pid>/stat.
+pub fn proc_stat_field(pid: &str, field: usize) -> io::Result<u64> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid))?;
+
+    // the command name in field 2 is in parentheses and may contain spaces
+    let rest = stat
+        .rfind(')')
+        .map(|end| &stat[end + 1..])
+        .ok_or_else(|| invalid("missing command name"))?;
+
+    rest.split_whitespace()
+        .nth(field - 3)
+        .ok_or_else(|| invalid("missing field"))?
+        .parse::<i64>()
+        .map(|value| value as u64)
+        .map_err(invalid)
+}
+
+fn invalid<E: ToString>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
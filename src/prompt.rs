@@ -0,0 +1,200 @@
+use std::ffi::c_int;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicI32, Ordering};
+
+use nix::sys::signal::{kill, sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use nix::sys::termios::{tcgetattr, tcsetattr, LocalFlags, SetArg, Termios};
+use nix::unistd::{getpid, User};
+
+use crate::user;
+
+const TTY_PATH: &str = "/dev/tty";
+
+// signals that would otherwise leave the terminal with echo turned off
+const SIGNALS: [Signal; 9] = [
+    Signal::SIGALRM,
+    Signal::SIGHUP,
+    Signal::SIGINT,
+    Signal::SIGPIPE,
+    Signal::SIGQUIT,
+    Signal::SIGTERM,
+    Signal::SIGTSTP,
+    Signal::SIGTTIN,
+    Signal::SIGTTOU,
+];
+
+static CAUGHT: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn catch_signal(signo: c_int) {
+    CAUGHT.store(signo, Ordering::SeqCst);
+}
+
+/// Where answers to prompts come from.
+#[derive(Debug, Clone, Default)]
+pub enum Prompter {
+    /// The controlling terminal.
+    #[default]
+    Tty,
+    /// A program run as `user` that shows the prompt and prints the answer, `-A`.
+    ///
+    /// The program must come from [`crate::secure::trusted_program`].
+    Askpass { program: PathBuf, user: User },
+}
+
+impl Prompter {
+    /// Ask for a single line that can be shown while it is typed.
+    #[cfg(feature = "pam")]
+    pub fn read_line(&self, prompt: &str) -> io::Result<String> {
+        match self {
+            Prompter::Tty => read_tty(prompt, true),
+            Prompter::Askpass { program, user } => askpass(program, user, prompt),
+        }
+    }
+
+    /// Ask for a password.
+    pub fn read_password(&self, prompt: &str) -> io::Result<String> {
+        match self {
+            Prompter::Tty => read_tty(prompt, false),
+            Prompter::Askpass { program, user } => askpass(program, user, prompt),
+        }
+    }
+}
+
+/// Run the askpass `program` with the prompt as its argument and read the answer from its output.
+///
+/// It runs with nothing but the privileges of `user`, since it is their password it is asking for.
+fn askpass(program: &Path, user: &User, prompt: &str) -> io::Result<String> {
+    let mut command = Command::new(program);
+    command
+        .arg(prompt)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit());
+    let user = user.clone();
+    unsafe {
+        command.pre_exec(move || user::become_user(&user).map_err(io::Error::from));
+    }
+
+    let output = command.output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "{} failed with {}",
+            program.display(),
+            output.status
+        )));
+    }
+
+    let mut answer = output.stdout;
+    if answer.last() == Some(&b'\n') {
+        answer.pop();
+    }
+    String::from_utf8(answer).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn read_tty(prompt: &str, echo: bool) -> io::Result<String> {
+    loop {
+        CAUGHT.store(0, Ordering::SeqCst);
+
+        let result = TtyGuard::new(echo).and_then(|guard| {
+            (&guard.tty).write_all(prompt.as_bytes())?;
+            read_until_newline(&guard.tty)
+        });
+
+        // the guard has restored the terminal and handlers by now so it is safe to deliver the signal
+        let caught = CAUGHT.load(Ordering::SeqCst);
+        if let Ok(signal) = Signal::try_from(caught) {
+            kill(getpid(), signal)?;
+
+            // job control stopped us and we have been continued, ask again
+            if matches!(signal, Signal::SIGTSTP | Signal::SIGTTIN | Signal::SIGTTOU) {
+                continue;
+            }
+        }
+
+        return result;
+    }
+}
+
+fn read_until_newline(mut tty: &File) -> io::Result<String> {
+    let mut line = Vec::new();
+    let mut byte = [0u8; 1];
+
+    loop {
+        match tty.read(&mut byte)? {
+            0 => break,
+            _ if byte[0] == b'\n' => break,
+            _ => line.push(byte[0]),
+        }
+    }
+
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+
+    String::from_utf8(line).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// The open terminal, restoring its original attributes and our signal handlers when dropped.
+struct TtyGuard {
+    tty: File,
+    termios: Option<Termios>,
+    handlers: Vec<(Signal, SigAction)>,
+}
+
+impl TtyGuard {
+    fn new(echo: bool) -> io::Result<TtyGuard> {
+        let tty = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY | libc::O_CLOEXEC)
+            .open(TTY_PATH)?;
+
+        let mut guard = TtyGuard {
+            tty,
+            termios: None,
+            handlers: Vec::with_capacity(SIGNALS.len()),
+        };
+
+        // no SA_RESTART so a signal interrupts the blocking read
+        let action = SigAction::new(
+            SigHandler::Handler(catch_signal),
+            SaFlags::empty(),
+            SigSet::empty(),
+        );
+        for signal in SIGNALS {
+            let old = unsafe { sigaction(signal, &action) }?;
+            guard.handlers.push((signal, old));
+        }
+
+        if !echo {
+            let fd = guard.tty.as_raw_fd();
+            let original = tcgetattr(fd)?;
+            let mut silent = original.clone();
+            silent.local_flags.remove(LocalFlags::ECHO);
+            silent.local_flags.insert(LocalFlags::ECHONL);
+
+            guard.termios = Some(original);
+            tcsetattr(fd, SetArg::TCSAFLUSH, &silent)?;
+        }
+
+        Ok(guard)
+    }
+}
+
+impl Drop for TtyGuard {
+    fn drop(&mut self) {
+        if let Some(termios) = &self.termios {
+            let _ = tcsetattr(self.tty.as_raw_fd(), SetArg::TCSAFLUSH, termios);
+        }
+
+        for (signal, action) in &self.handlers {
+            let _ = unsafe { sigaction(*signal, action) };
+        }
+    }
+}
@@ -0,0 +1,1235 @@
+use std::ffi::{CString, OsStr, OsString};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use nix::unistd::{Uid, User};
+
+use crate::config::{Action, Caller, Config, Decision, Options, Request, Rule};
+use crate::env;
+use crate::error::Error;
+use crate::path;
+use crate::syslog::{Attempt, Audit};
+
+/// The passwd and group databases.
+pub trait UserDb {
+    fn by_uid(&self, uid: Uid) -> nix::Result<Option<User>>;
+
+    fn by_name(&self, name: &str) -> nix::Result<Option<User>>;
+
+    /// Describe `user` with the names of all the groups they are in.
+    fn caller(&self, user: &User) -> nix::Result<Caller>;
+
+    /// Whether `shell` is one of the login shells in /etc/shells.
+    fn is_login_shell(&self, shell: &OsStr) -> bool;
+
+    /// The names this machine goes by, for rules limited to some hosts.
+    fn host_names(&self) -> Vec<String>;
+}
+
+/// Checks the caller is who they say they are.
+pub trait Authenticator {
+    /// Proof of a successful authentication, kept until the command has run.
+    type Session;
+
+    /// Whether `user` authenticated on this terminal session less than `timeout` ago.
+    fn persisted(&self, user: &User, timeout: Duration) -> bool;
+
+    /// Remember that `user` just authenticated on this terminal session.
+    fn remember(&self, user: &User) -> io::Result<()>;
+
+    /// Authenticate `user`, only asking for a password when `prompt` is set.
+    fn authenticate(&mut self, user: &User, prompt: bool) -> Result<Self::Session, Error>;
+}
+
+/// Wall clock time, used to throttle failed authentications.
+pub trait Clock {
+    /// Time since the epoch.
+    fn now(&self) -> Duration;
+
+    fn sleep(&self, duration: Duration);
+}
+
+/// Finds and runs commands.
+pub trait Executor<S> {
+    /// The absolute path of the file `command` runs, names are searched for in `search_path`.
+    fn resolve(&self, command: &OsStr, search_path: &OsStr) -> io::Result<PathBuf>;
+
+    /// Run the command as `target` and return its exit status.
+    fn execute(&mut self, session: S, target: &User, execution: &Execution) -> Result<i32, Error>;
+
+    /// Let `user` edit copies of the files and write back the ones that changed as `target`.
+    fn edit(&mut self, session: S, user: &User, target: &User, edit: &Edit) -> Result<i32, Error>;
+}
+
+/// What to run as the target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mode {
+    Command(Vec<OsString>),
+    /// `-s`, the target's shell or the caller's `$SHELL` when it is a login shell.
+    Shell,
+    /// `-i`, the target's shell as a login shell in their home directory.
+    Login,
+    /// `-e`, edit the files as the target.
+    Edit(Vec<OsString>),
+}
+
+/// Everything about a single run of execas once the users involved are known.
+#[derive(Debug, Clone)]
+pub struct Invocation {
+    /// The real user running execas.
+    pub user: User,
+    pub caller: Caller,
+    pub target: User,
+    /// The command as typed, its first element is what the command sees as `argv[0]`.
+    pub command: Vec<OsString>,
+    pub login: bool,
+    /// The names of this machine.
+    pub hosts: Vec<String>,
+    /// `command` is the files to edit.
+    pub edit: bool,
+    /// Fail instead of asking for a password, `-n`.
+    pub non_interactive: bool,
+    /// The caller's environment.
+    pub environment: Vec<(OsString, OsString)>,
+}
+
+impl Invocation {
+    fn var(&self, name: &str) -> Option<&OsStr> {
+        self.environment
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_os_str())
+    }
+}
+
+/// The exact program, arguments, environment and directory the command runs with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Execution {
+    pub path: CString,
+    pub args: Vec<CString>,
+    pub env: Vec<CString>,
+    pub cwd: Option<PathBuf>,
+}
+
+/// The files to edit and the caller's editor to do it with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edit {
+    /// The editor and any arguments it needs, the files are added to the end.
+    pub editor: Vec<OsString>,
+    /// Absolute paths.
+    pub files: Vec<PathBuf>,
+}
+
+/// What `-l` shows.
+#[derive(Debug)]
+pub struct Listing<'c> {
+    /// The rules applying to the caller in the order they appear.
+    pub rules: Vec<&'c Rule>,
+    /// What the rules decide for the command, when one was given.
+    pub decision: Option<Decision<'c>>,
+}
+
+/// The whole flow from looking up the users to running the command.
+pub struct Runner<U, A, E, L> {
+    pub users: U,
+    pub auth: A,
+    pub executor: E,
+    pub audit: L,
+}
+
+impl<U, A, E, L> Runner<U, A, E, L>
+where
+    U: UserDb,
+    A: Authenticator,
+    E: Executor<A::Session>,
+    L: Audit,
+{
+    /// Look up the real user `uid` and the `target` they want to run `mode` as.
+    ///
+    /// A failed lookup is logged as a denied attempt so probing for users leaves a trace too.
+    pub fn invocation(
+        &self,
+        uid: Uid,
+        target: &str,
+        mode: Mode,
+        environment: Vec<(OsString, OsString)>,
+    ) -> Result<Invocation, Error> {
+        let command = match &mode {
+            Mode::Command(command) => logged(false, command),
+            Mode::Edit(files) => logged(true, files),
+            Mode::Shell | Mode::Login => Vec::new(),
+        };
+
+        self.lookup(uid, target, mode, environment)
+            .inspect_err(|err| {
+                let caller = match self.users.by_uid(uid) {
+                    Ok(Some(user)) => user.name,
+                    _ => format!("#{}", uid),
+                };
+                let attempt = Attempt {
+                    caller: &caller,
+                    target,
+                    command: &command,
+                };
+                self.audit.denied(&attempt, &err.to_string());
+            })
+    }
+
+    fn lookup(
+        &self,
+        uid: Uid,
+        target: &str,
+        mode: Mode,
+        environment: Vec<(OsString, OsString)>,
+    ) -> Result<Invocation, Error> {
+        // the invoking user is whoever owns the process, not who we are acting as
+        let user = match self.users.by_uid(uid) {
+            Ok(Some(user)) => user,
+            Ok(None) => return Err(Error::Lookup(format!("unknown user id {}", uid))),
+            Err(err) => {
+                return Err(Error::Lookup(format!(
+                    "failed to look up user id {}: {}",
+                    uid, err
+                )))
+            }
+        };
+
+        let caller = self.users.caller(&user).map_err(|err| {
+            Error::Lookup(format!(
+                "failed to look up the groups of {}: {}",
+                user.name, err
+            ))
+        })?;
+
+        let target = match self.users.by_name(target) {
+            Ok(Some(user)) => user,
+            Ok(None) => return Err(Error::Lookup(format!("unknown user {}", target))),
+            Err(err) => {
+                return Err(Error::Lookup(format!(
+                    "failed to look up user {}: {}",
+                    target, err
+                )))
+            }
+        };
+
+        let mut invocation = Invocation {
+            user,
+            caller,
+            target,
+            command: Vec::new(),
+            login: mode == Mode::Login,
+            hosts: self.users.host_names(),
+            edit: matches!(mode, Mode::Edit(_)),
+            non_interactive: false,
+            environment,
+        };
+
+        // shells are checked against the rules like any other command
+        invocation.command = match mode {
+            Mode::Command(command) | Mode::Edit(command) => command,
+            Mode::Shell => vec![self.shell_for(&invocation, true)],
+            Mode::Login => vec![self.shell_for(&invocation, false)],
+        };
+
+        Ok(invocation)
+    }
+
+    /// Build the request the config decides on, with the command resolved to the file that runs.
+    ///
+    /// Commands without a path are looked up in the `secure_path` of `config` and never the callers
+    /// `PATH`, so a caller can not match a rule with a program of their own earlier in it.
+    pub fn request(&self, config: &Config, invocation: &Invocation) -> Result<Request, Error> {
+        let mut command = invocation.command.clone();
+        if invocation.edit {
+            for file in &mut command {
+                *file = edit_path(file)?.into_os_string();
+            }
+        } else if let Some(first) = command.first_mut() {
+            let search_path = OsStr::new(&config.settings.secure_path);
+            *first = self
+                .executor
+                .resolve(first, search_path)
+                .map_err(Error::Command)?
+                .into_os_string();
+        }
+
+        Ok(Request {
+            caller: invocation.caller.clone(),
+            target: invocation.target.name.clone(),
+            command,
+            edit: invocation.edit,
+            hosts: invocation.hosts.clone(),
+        })
+    }
+
+    /// Check the invocation against `config`, authenticate the caller and run the command.
+    ///
+    /// Returns the exit status of the command.
+    pub fn run(&mut self, config: &Config, invocation: &Invocation) -> Result<i32, Error> {
+        // rules are matched against the file that will actually run rather than what argv[0] says
+        let request = self.request(config, invocation).and_then(|request| {
+            if request.command.is_empty() {
+                return Err(Error::Failed("no command given".to_string()));
+            }
+            Ok(request)
+        });
+        // commands that can not be found are logged too, as typed
+        let request = request.map_err(|err| self.refuse(invocation, err))?;
+
+        let command = logged(request.edit, &request.command);
+        let attempt = Attempt {
+            caller: &invocation.user.name,
+            target: &invocation.target.name,
+            command: &command,
+        };
+
+        let decision = config.evaluate(&request, |command, search_path| {
+            self.executor.resolve(command, search_path)
+        });
+        let rule = match decision {
+            Decision::Permit(rule) => rule,
+            Decision::Deny(Some(rule)) => {
+                if !rule.options.nolog_failure {
+                    self.audit.denied(
+                        &attempt,
+                        &format!("denied by the rule on {}", rule.location()),
+                    );
+                }
+                return Err(Error::Denied);
+            }
+            Decision::Deny(None) => {
+                self.audit.denied(&attempt, "no matching rule");
+                return Err(Error::Denied);
+            }
+        };
+
+        // a recent authentication on this session counts for persist rules
+        let persisted = rule
+            .options
+            .persist
+            .is_some_and(|timeout| self.auth.persisted(&invocation.user, timeout));
+        let prompt = !rule.options.nopass && !persisted;
+
+        // force the user to reauthenticate unless the rule says otherwise
+        let session = match self.authenticate(invocation, prompt) {
+            Ok(session) => session,
+            Err(err) => {
+                if !rule.options.nolog_failure {
+                    self.audit.denied(&attempt, &err.to_string());
+                }
+                return Err(err);
+            }
+        };
+
+        // only a real authentication starts a new period, using it does not extend it
+        if prompt && rule.options.persist.is_some() {
+            if let Err(err) = self.auth.remember(&invocation.user) {
+                eprintln!("execas: failed to record the authentication: {}", err);
+            }
+        }
+
+        if !rule.options.nolog {
+            self.audit.permitted(
+                &attempt,
+                &format!("permitted by the rule on {}", rule.location()),
+            );
+        }
+
+        if request.edit {
+            let edit = Edit {
+                editor: editor(invocation),
+                files: request.command.iter().map(PathBuf::from).collect(),
+            };
+            return self
+                .executor
+                .edit(session, &invocation.user, &invocation.target, &edit);
+        }
+
+        let execution = execution(config, rule, invocation, &request);
+        self.executor
+            .execute(session, &invocation.target, &execution)
+    }
+
+    /// Authenticate the caller for `-l` and collect the rules that apply to them, with what they
+    /// decide for the command when one was given.
+    ///
+    /// A nopass rule does not let anyone at the terminal read the rules, only a recent
+    /// authentication for a persist rule saves asking for the password. Failures and decisions are
+    /// logged like those of a real run.
+    pub fn list<'c>(
+        &mut self,
+        config: &'c Config,
+        invocation: &Invocation,
+    ) -> Result<Listing<'c>, Error> {
+        let rules: Vec<&Rule> = config
+            .rules
+            .iter()
+            .filter(|rule| rule.applies_to(&invocation.caller, &invocation.hosts))
+            .collect();
+        if rules.is_empty() {
+            let err = Error::Failed(format!("no rules apply to {}", invocation.user.name));
+            return Err(self.refuse(invocation, err));
+        }
+
+        let persisted = rules
+            .iter()
+            .filter(|rule| rule.action == Action::Permit)
+            .filter_map(|rule| rule.options.persist)
+            .any(|timeout| self.auth.persisted(&invocation.user, timeout));
+        if let Err(err) = self.authenticate(invocation, !persisted) {
+            return Err(self.refuse(invocation, err));
+        }
+
+        if invocation.command.is_empty() {
+            return Ok(Listing {
+                rules,
+                decision: None,
+            });
+        }
+
+        // a command that can not be found is still worth asking about
+        let request = self
+            .request(config, invocation)
+            .unwrap_or_else(|_| Request {
+                caller: invocation.caller.clone(),
+                target: invocation.target.name.clone(),
+                command: invocation.command.clone(),
+                edit: invocation.edit,
+                hosts: invocation.hosts.clone(),
+            });
+        let decision = config.evaluate(&request, |command, search_path| {
+            self.executor.resolve(command, search_path)
+        });
+
+        let mut command = vec![OsString::from("list")];
+        command.extend(logged(request.edit, &request.command));
+        let attempt = Attempt {
+            caller: &invocation.user.name,
+            target: &invocation.target.name,
+            command: &command,
+        };
+        match &decision {
+            Decision::Permit(rule) if !rule.options.nolog => self.audit.permitted(
+                &attempt,
+                &format!("permitted by the rule on {}", rule.location()),
+            ),
+            Decision::Permit(_) => {}
+            Decision::Deny(Some(rule)) if !rule.options.nolog_failure => self.audit.denied(
+                &attempt,
+                &format!("denied by the rule on {}", rule.location()),
+            ),
+            Decision::Deny(Some(_)) => {}
+            Decision::Deny(None) => self.audit.denied(&attempt, "no matching rule"),
+        }
+
+        Ok(Listing {
+            rules,
+            decision: Some(decision),
+        })
+    }
+
+    /// Log that the invocation was refused with `err`, the command as typed, and hand `err` back.
+    pub fn refuse(&self, invocation: &Invocation, err: Error) -> Error {
+        let attempt = Attempt {
+            caller: &invocation.user.name,
+            target: &invocation.target.name,
+            command: &logged(invocation.edit, &invocation.command),
+        };
+        self.audit.denied(&attempt, &err.to_string());
+        err
+    }
+
+    /// Authenticate the caller, failing rather than asking for a password with `-n`.
+    pub fn authenticate(
+        &mut self,
+        invocation: &Invocation,
+        prompt: bool,
+    ) -> Result<A::Session, Error> {
+        if prompt && invocation.non_interactive {
+            return Err(Error::Authentication("a password is required".to_string()));
+        }
+
+        self.auth.authenticate(&invocation.user, prompt)
+    }
+
+    /// Pick the shell to run for the target.
+    ///
+    /// With `-s` the callers `$SHELL` is used when it is one of the login shells in /etc/shells, login
+    /// shells and everything else use the shell from the targets passwd entry.
+    fn shell_for(&self, invocation: &Invocation, shell: bool) -> OsString {
+        if shell {
+            if let Some(caller_shell) = invocation.var("SHELL") {
+                if self.users.is_login_shell(caller_shell) {
+                    return caller_shell.to_os_string();
+                }
+            }
+        }
+
+        let target = &invocation.target;
+        if target.shell.as_os_str().is_empty() {
+            OsString::from("/bin/sh")
+        } else {
+            target.shell.clone().into_os_string()
+        }
+    }
+}
+
+/// The command as it is logged, edits the way an edit rule reads.
+fn logged(edit: bool, command: &[OsString]) -> Vec<OsString> {
+    let mut logged = Vec::new();
+    if edit {
+        logged.push(OsString::from("edit"));
+    }
+    logged.extend(command.iter().cloned());
+    logged
+}
+
+/// The absolute path of a file to edit, only the directories leading to it are resolved.
+pub fn edit_path(file: &OsStr) -> Result<PathBuf, Error> {
+    let file = Path::new(file);
+    if file.is_absolute() {
+        return Ok(path::normalize(file));
+    }
+
+    let cwd = std::env::current_dir()
+        .map_err(|err| Error::Failed(format!("failed to get the working directory: {}", err)))?;
+    Ok(path::normalize(&cwd.join(file)))
+}
+
+/// The caller's `$VISUAL` or `$EDITOR` split into words, falling back to vi.
+fn editor(invocation: &Invocation) -> Vec<OsString> {
+    ["VISUAL", "EDITOR"]
+        .iter()
+        .filter_map(|name| invocation.var(name))
+        .map(|editor| {
+            editor
+                .as_bytes()
+                .split(|byte| byte.is_ascii_whitespace())
+                .filter(|word| !word.is_empty())
+                .map(|word| OsStr::from_bytes(word).to_os_string())
+                .collect::<Vec<_>>()
+        })
+        .find(|words| !words.is_empty())
+        .unwrap_or_else(|| vec![OsString::from("vi")])
+}
+
+/// Work out exactly what to run for a request permitted by `rule`.
+fn execution(
+    config: &Config,
+    rule: &Rule,
+    invocation: &Invocation,
+    request: &Request,
+) -> Execution {
+    let environment = invocation.environment.iter().cloned();
+    let search_path = &config.settings.secure_path;
+
+    // never hand the callers environment to the command unless the rule allows it, a login shell
+    // starts from a clean environment like it would after logging in
+    let env = if invocation.login {
+        let options = Options {
+            keepenv: false,
+            ..rule.options.clone()
+        };
+        env::build(
+            &options,
+            &invocation.user,
+            &invocation.target,
+            environment,
+            search_path,
+        )
+    } else {
+        env::build(
+            &rule.options,
+            &invocation.user,
+            &invocation.target,
+            environment,
+            search_path,
+        )
+    };
+
+    let command = &invocation.command;
+    let mut args: Vec<CString> = command
+        .iter()
+        .map(|arg| CString::new(arg.as_bytes()).expect("Arguments may not contain nul bytes"))
+        .collect();
+    let path = CString::new(request.command[0].as_bytes()).expect("paths have no nul bytes");
+
+    // login shells are told so by a leading dash in argv[0]
+    let cwd = if invocation.login {
+        let name = Path::new(&command[0])
+            .file_name()
+            .unwrap_or(command[0].as_os_str());
+        let mut argv0 = b"-".to_vec();
+        argv0.extend_from_slice(name.as_bytes());
+        args[0] = CString::new(argv0).expect("paths have no nul bytes");
+        Some(invocation.target.dir.clone())
+    } else {
+        None
+    };
+
+    Execution {
+        path,
+        args,
+        env,
+        cwd,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+
+    use nix::unistd::Gid;
+
+    use super::*;
+
+    fn user(name: &str, uid: u32, shell: &str) -> User {
+        User {
+            name: name.to_string(),
+            passwd: CString::new("x").unwrap(),
+            uid: Uid::from_raw(uid),
+            gid: Gid::from_raw(uid),
+            gecos: CString::default(),
+            dir: PathBuf::from(format!("/home/{}", name)),
+            shell: PathBuf::from(shell),
+        }
+    }
+
+    struct Users(Vec<User>);
+
+    impl UserDb for Users {
+        fn by_uid(&self, uid: Uid) -> nix::Result<Option<User>> {
+            Ok(self.0.iter().find(|user| user.uid == uid).cloned())
+        }
+
+        fn by_name(&self, name: &str) -> nix::Result<Option<User>> {
+            Ok(self.0.iter().find(|user| user.name == name).cloned())
+        }
+
+        fn caller(&self, user: &User) -> nix::Result<Caller> {
+            Ok(Caller {
+                name: user.name.clone(),
+                groups: vec![user.name.clone(), "wheel".to_string()],
+            })
+        }
+
+        fn is_login_shell(&self, shell: &OsStr) -> bool {
+            shell == "/bin/sh" || shell == "/bin/zsh"
+        }
+
+        fn host_names(&self) -> Vec<String> {
+            vec!["db1.example.com".to_string(), "db1".to_string()]
+        }
+    }
+
+    #[derive(Default)]
+    struct Auth {
+        wrong_password: bool,
+        persisted: bool,
+        prompts: Vec<bool>,
+        remembered: Cell<usize>,
+    }
+
+    impl Authenticator for Auth {
+        type Session = ();
+
+        fn persisted(&self, _user: &User, _timeout: Duration) -> bool {
+            self.persisted
+        }
+
+        fn remember(&self, _user: &User) -> io::Result<()> {
+            self.remembered.set(self.remembered.get() + 1);
+            Ok(())
+        }
+
+        fn authenticate(&mut self, _user: &User, prompt: bool) -> Result<(), Error> {
+            self.prompts.push(prompt);
+            if prompt && self.wrong_password {
+                return Err(Error::Authentication("Authentication failed".to_string()));
+            }
+            Ok(())
+        }
+    }
+
+    /// Pretends the listed files are the only executables.
+    struct Commands {
+        files: Vec<&'static str>,
+        executed: Option<(String, Execution)>,
+        edited: Option<(String, String, Edit)>,
+    }
+
+    impl Executor<()> for Commands {
+        fn resolve(&self, command: &OsStr, search_path: &OsStr) -> io::Result<PathBuf> {
+            let candidates: Vec<PathBuf> = if command.as_bytes().contains(&b'/') {
+                vec![PathBuf::from(command)]
+            } else {
+                std::env::split_paths(search_path)
+                    .map(|dir| dir.join(command))
+                    .collect()
+            };
+
+            candidates
+                .into_iter()
+                .find(|path| self.files.iter().any(|file| path == Path::new(file)))
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "command not found"))
+        }
+
+        fn execute(
+            &mut self,
+            _session: (),
+            target: &User,
+            execution: &Execution,
+        ) -> Result<i32, Error> {
+            self.executed = Some((target.name.clone(), execution.clone()));
+            Ok(0)
+        }
+
+        fn edit(
+            &mut self,
+            _session: (),
+            user: &User,
+            target: &User,
+            edit: &Edit,
+        ) -> Result<i32, Error> {
+            self.edited = Some((user.name.clone(), target.name.clone(), edit.clone()));
+            Ok(0)
+        }
+    }
+
+    #[derive(Default)]
+    struct Log(RefCell<Vec<String>>);
+
+    impl Audit for Log {
+        fn permitted(&self, attempt: &Attempt, reason: &str) {
+            self.0
+                .borrow_mut()
+                .push(format!("permit {} {}", attempt.caller, reason));
+        }
+
+        fn denied(&self, attempt: &Attempt, reason: &str) {
+            self.0
+                .borrow_mut()
+                .push(format!("deny {} {}", attempt.caller, reason));
+        }
+    }
+
+    type Test = Runner<Users, Auth, Commands, Log>;
+
+    fn fake() -> Test {
+        Runner {
+            users: Users(vec![
+                user("root", 0, "/bin/bash"),
+                user("alice", 1000, "/bin/zsh"),
+            ]),
+            auth: Auth::default(),
+            executor: Commands {
+                files: vec!["/usr/bin/id", "/usr/bin/env", "/bin/bash", "/bin/zsh"],
+                executed: None,
+                edited: None,
+            },
+            audit: Log::default(),
+        }
+    }
+
+    fn environment(vars: &[(&str, &str)]) -> Vec<(OsString, OsString)> {
+        vars.iter()
+            .map(|(name, value)| (OsString::from(name), OsString::from(value)))
+            .collect()
+    }
+
+    fn command(args: &[&str]) -> Mode {
+        Mode::Command(args.iter().map(OsString::from).collect())
+    }
+
+    fn run(
+        runner: &mut Test,
+        config: &str,
+        mode: Mode,
+        vars: &[(&str, &str)],
+    ) -> Result<i32, Error> {
+        let config = Config::parse(config).unwrap();
+        let invocation = runner.invocation(Uid::from_raw(1000), "root", mode, environment(vars))?;
+        runner.run(&config, &invocation)
+    }
+
+    fn cstrings(values: &[&str]) -> Vec<CString> {
+        values
+            .iter()
+            .map(|value| CString::new(*value).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn runs_a_permitted_command() {
+        let mut runner = fake();
+        let result = run(
+            &mut runner,
+            "permit alice as root cmd id\n",
+            command(&["id", "-u"]),
+            &[("PATH", "/usr/bin"), ("TERM", "xterm"), ("SECRET", "1")],
+        );
+
+        assert_eq!(result.unwrap(), 0);
+        assert_eq!(runner.auth.prompts, vec![true]);
+
+        let (target, execution) = runner.executor.executed.unwrap();
+        assert_eq!(target, "root");
+        assert_eq!(execution.path, CString::new("/usr/bin/id").unwrap());
+        assert_eq!(execution.args, cstrings(&["id", "-u"]));
+        assert_eq!(execution.cwd, None);
+        assert_eq!(
+            execution.env,
+            cstrings(&[
+                "EXECAS_USER=alice",
+                "HOME=/home/root",
+                "LOGNAME=root",
+                &format!("PATH={}", env::SAFE_PATH),
+                "SHELL=/bin/bash",
+                "TERM=xterm",
+                "USER=root",
+            ])
+        );
+
+        assert_eq!(
+            *runner.audit.0.borrow(),
+            vec!["permit alice permitted by the rule on line 1"]
+        );
+    }
+
+    #[test]
+    fn refuses_without_a_matching_rule() {
+        let mut runner = fake();
+        let result = run(
+            &mut runner,
+            "permit alice cmd env\n",
+            command(&["/usr/bin/id"]),
+            &[],
+        );
+
+        assert!(matches!(result, Err(Error::Denied)));
+        assert!(runner.auth.prompts.is_empty());
+        assert!(runner.executor.executed.is_none());
+        assert_eq!(
+            *runner.audit.0.borrow(),
+            vec!["deny alice no matching rule"]
+        );
+    }
+
+    #[test]
+    fn deny_rules_can_skip_the_log() {
+        let mut runner = fake();
+        let result = run(
+            &mut runner,
+            "permit alice\ndeny nolog_failure alice cmd id\n",
+            command(&["/usr/bin/id"]),
+            &[],
+        );
+
+        assert!(matches!(result, Err(Error::Denied)));
+        assert!(runner.audit.0.borrow().is_empty());
+    }
+
+    #[test]
+    fn failed_authentication_does_not_run_the_command() {
+        let mut runner = fake();
+        runner.auth.wrong_password = true;
+        let result = run(
+            &mut runner,
+            "permit alice\n",
+            command(&["/usr/bin/id"]),
+            &[],
+        );
+
+        assert!(matches!(result, Err(Error::Authentication(_))));
+        assert!(runner.executor.executed.is_none());
+        assert_eq!(
+            *runner.audit.0.borrow(),
+            vec!["deny alice Authentication failed"]
+        );
+    }
+
+    #[test]
+    fn nopass_and_persist_skip_the_prompt() {
+        let mut runner = fake();
+        run(
+            &mut runner,
+            "permit nopass nolog alice\n",
+            command(&["/usr/bin/id"]),
+            &[],
+        )
+        .unwrap();
+        assert_eq!(runner.auth.prompts, vec![false]);
+        assert!(runner.audit.0.borrow().is_empty());
+
+        // the first authentication is remembered, later ones are not asked for
+        let mut runner = fake();
+        run(
+            &mut runner,
+            "permit persist alice\n",
+            command(&["/usr/bin/id"]),
+            &[],
+        )
+        .unwrap();
+        assert_eq!(runner.auth.prompts, vec![true]);
+        assert_eq!(runner.auth.remembered.get(), 1);
+
+        let mut runner = fake();
+        runner.auth.persisted = true;
+        run(
+            &mut runner,
+            "permit persist alice\n",
+            command(&["/usr/bin/id"]),
+            &[],
+        )
+        .unwrap();
+        assert_eq!(runner.auth.prompts, vec![false]);
+        assert_eq!(runner.auth.remembered.get(), 0);
+    }
+
+    #[test]
+    fn non_interactive_fails_instead_of_prompting() {
+        let config = Config::parse("permit alice cmd id\npermit nopass alice cmd env\n").unwrap();
+        let mut runner = fake();
+        let mut invocation = runner
+            .invocation(
+                Uid::from_raw(1000),
+                "root",
+                command(&["/usr/bin/id"]),
+                Vec::new(),
+            )
+            .unwrap();
+        invocation.non_interactive = true;
+
+        let err = runner.run(&config, &invocation).unwrap_err();
+        assert_eq!(err.to_string(), "a password is required");
+        assert_eq!(err.exit_code(), 1);
+        assert!(runner.auth.prompts.is_empty());
+        assert!(runner.executor.executed.is_none());
+
+        // nothing has to be asked for a nopass rule or a persisted authentication
+        invocation.command = vec![OsString::from("/usr/bin/env")];
+        assert_eq!(runner.run(&config, &invocation).unwrap(), 0);
+
+        let config = Config::parse("permit persist alice\n").unwrap();
+        let mut runner = fake();
+        runner.auth.persisted = true;
+        assert_eq!(runner.run(&config, &invocation).unwrap(), 0);
+        assert_eq!(runner.auth.prompts, vec![false]);
+    }
+
+    #[test]
+    fn resolves_commands_through_the_safe_path_by_default() {
+        let mut runner = fake();
+        runner.executor.files.push("/tmp/id");
+        run(
+            &mut runner,
+            "permit alice cmd id\n",
+            command(&["id"]),
+            &[("PATH", "/tmp:/usr/bin")],
+        )
+        .unwrap();
+
+        let (_, execution) = runner.executor.executed.unwrap();
+        assert_eq!(execution.path, CString::new("/usr/bin/id").unwrap());
+
+        // a command that is only in the callers PATH is not found at all
+        let mut runner = fake();
+        runner.executor.files.push("/tmp/tool");
+        let result = run(
+            &mut runner,
+            "permit alice\n",
+            command(&["tool"]),
+            &[("PATH", "/tmp")],
+        );
+        assert_eq!(result.unwrap_err().exit_code(), 127);
+    }
+
+    #[test]
+    fn secure_path_ignores_the_callers_path() {
+        let mut runner = fake();
+        runner.executor.files.push("/home/alice/bin/id");
+        let config = "set secure_path /usr/bin\npermit alice cmd id\n";
+        run(
+            &mut runner,
+            config,
+            command(&["id"]),
+            &[("PATH", "/home/alice/bin:/usr/bin")],
+        )
+        .unwrap();
+
+        let (_, execution) = runner.executor.executed.unwrap();
+        assert_eq!(execution.path, CString::new("/usr/bin/id").unwrap());
+        assert!(execution
+            .env
+            .contains(&CString::new("PATH=/usr/bin").unwrap()));
+
+        // a command that is not in the secure path is not found at all
+        let mut runner = fake();
+        let result = run(&mut runner, config, command(&["bash"]), &[("PATH", "/bin")]);
+        assert_eq!(result.unwrap_err().exit_code(), 127);
+    }
+
+    #[test]
+    fn login_shells_start_fresh_in_the_targets_home() {
+        let mut runner = fake();
+        run(
+            &mut runner,
+            "permit keepenv alice\n",
+            Mode::Login,
+            &[("SECRET", "1"), ("SHELL", "/bin/zsh")],
+        )
+        .unwrap();
+
+        let (_, execution) = runner.executor.executed.unwrap();
+        assert_eq!(execution.path, CString::new("/bin/bash").unwrap());
+        assert_eq!(execution.args, cstrings(&["-bash"]));
+        assert_eq!(execution.cwd, Some(PathBuf::from("/home/root")));
+        assert!(!execution.env.contains(&CString::new("SECRET=1").unwrap()));
+    }
+
+    #[test]
+    fn shell_mode_prefers_the_callers_login_shell() {
+        let mut runner = fake();
+        run(
+            &mut runner,
+            "permit alice\n",
+            Mode::Shell,
+            &[("SHELL", "/bin/zsh")],
+        )
+        .unwrap();
+        let (_, execution) = runner.executor.executed.unwrap();
+        assert_eq!(execution.path, CString::new("/bin/zsh").unwrap());
+
+        let mut runner = fake();
+        run(
+            &mut runner,
+            "permit alice\n",
+            Mode::Shell,
+            &[("SHELL", "/tmp/evil")],
+        )
+        .unwrap();
+        let (_, execution) = runner.executor.executed.unwrap();
+        assert_eq!(execution.path, CString::new("/bin/bash").unwrap());
+    }
+
+    #[test]
+    fn edits_files_permitted_by_an_edit_rule() {
+        let mut runner = fake();
+        let config = "permit alice edit /etc/motd\n";
+        run(
+            &mut runner,
+            config,
+            Mode::Edit(vec![OsString::from("/etc/../etc/motd")]),
+            &[("EDITOR", "nano -w")],
+        )
+        .unwrap();
+
+        let (user, target, edit) = runner.executor.edited.unwrap();
+        assert_eq!((user.as_str(), target.as_str()), ("alice", "root"));
+        assert_eq!(edit.files, vec![PathBuf::from("/etc/motd")]);
+        assert_eq!(
+            edit.editor,
+            vec![OsString::from("nano"), OsString::from("-w")]
+        );
+        assert!(runner.executor.executed.is_none());
+        assert_eq!(
+            *runner.audit.0.borrow(),
+            vec!["permit alice permitted by the rule on line 1"]
+        );
+
+        // other files are not covered
+        let mut runner = fake();
+        let result = run(
+            &mut runner,
+            config,
+            Mode::Edit(vec![
+                OsString::from("/etc/motd"),
+                OsString::from("/etc/shadow"),
+            ]),
+            &[],
+        );
+        assert!(matches!(result, Err(Error::Denied)));
+    }
+
+    #[test]
+    fn command_rules_do_not_allow_editing() {
+        let mut runner = fake();
+        let result = run(
+            &mut runner,
+            "permit alice cmd vi\n",
+            Mode::Edit(vec![OsString::from("/etc/motd")]),
+            &[],
+        );
+        assert!(matches!(result, Err(Error::Denied)));
+        assert!(runner.executor.edited.is_none());
+
+        // and edit rules do not allow running anything
+        let mut runner = fake();
+        let result = run(
+            &mut runner,
+            "permit alice edit /etc/motd\n",
+            command(&["/usr/bin/id"]),
+            &[],
+        );
+        assert!(matches!(result, Err(Error::Denied)));
+    }
+
+    #[test]
+    fn editor_falls_back_to_vi() {
+        let mut runner = fake();
+        run(
+            &mut runner,
+            "permit alice\n",
+            Mode::Edit(vec![OsString::from("/etc/motd")]),
+            &[("VISUAL", " "), ("EDITOR", "")],
+        )
+        .unwrap();
+        let (_, _, edit) = runner.executor.edited.unwrap();
+        assert_eq!(edit.editor, vec![OsString::from("vi")]);
+    }
+
+    #[test]
+    fn host_rules_only_apply_on_matching_machines() {
+        let mut runner = fake();
+        run(
+            &mut runner,
+            "permit alice on db*.example.com cmd id\n",
+            command(&["/usr/bin/id"]),
+            &[],
+        )
+        .unwrap();
+        assert!(runner.executor.executed.is_some());
+
+        let mut runner = fake();
+        let result = run(
+            &mut runner,
+            "permit alice on web* cmd id\n",
+            command(&["/usr/bin/id"]),
+            &[],
+        );
+        assert!(matches!(result, Err(Error::Denied)));
+    }
+
+    #[test]
+    fn unknown_targets_are_an_error() {
+        let runner = fake();
+        let result = runner.invocation(Uid::from_raw(1000), "bob", command(&["id"]), Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "unknown user bob");
+        assert_eq!(
+            *runner.audit.0.borrow(),
+            vec!["deny alice unknown user bob"]
+        );
+
+        let result = runner.invocation(Uid::from_raw(4242), "root", command(&["id"]), Vec::new());
+        assert_eq!(result.unwrap_err().exit_code(), 5);
+        assert_eq!(
+            runner.audit.0.borrow().last().unwrap(),
+            "deny #4242 unknown user id 4242"
+        );
+    }
+
+    #[test]
+    fn commands_that_are_not_found_are_logged() {
+        let mut runner = fake();
+        let result = run(&mut runner, "permit alice\n", command(&["nosuchcmd"]), &[]);
+        assert_eq!(result.unwrap_err().exit_code(), 127);
+        assert_eq!(
+            *runner.audit.0.borrow(),
+            vec!["deny alice command not found"]
+        );
+    }
+
+    #[test]
+    fn listing_always_asks_for_the_password() {
+        let config = Config::parse(
+            "permit nopass alice cmd id
+deny alice cmd env
+",
+        )
+        .unwrap();
+        let mut runner = fake();
+        let invocation = runner
+            .invocation(Uid::from_raw(1000), "root", command(&[]), Vec::new())
+            .unwrap();
+
+        let listing = runner.list(&config, &invocation).unwrap();
+        assert_eq!(listing.rules.len(), 2);
+        assert_eq!(listing.decision, None);
+        assert_eq!(runner.auth.prompts, vec![true]);
+
+        // only a persisted authentication saves the prompt
+        let config = Config::parse(
+            "permit persist alice
+",
+        )
+        .unwrap();
+        let mut runner = fake();
+        runner.auth.persisted = true;
+        runner.list(&config, &invocation).unwrap();
+        assert_eq!(runner.auth.prompts, vec![false]);
+    }
+
+    #[test]
+    fn listing_is_logged() {
+        let config = Config::parse(
+            "permit nopass alice cmd id
+deny alice cmd env
+",
+        )
+        .unwrap();
+        let mut runner = fake();
+        runner.auth.wrong_password = true;
+        let invocation = runner
+            .invocation(Uid::from_raw(1000), "root", command(&["id"]), Vec::new())
+            .unwrap();
+        let err = runner.list(&config, &invocation).unwrap_err();
+        assert!(matches!(err, Error::Authentication(_)));
+        assert_eq!(
+            *runner.audit.0.borrow(),
+            vec!["deny alice Authentication failed"]
+        );
+
+        let mut runner = fake();
+        for args in [&["id"][..], &["env"], &["bash"]] {
+            let mut invocation = invocation.clone();
+            invocation.command = args.iter().map(OsString::from).collect();
+            let listing = runner.list(&config, &invocation).unwrap();
+            assert!(listing.decision.is_some());
+        }
+        assert!(runner.executor.executed.is_none());
+        assert_eq!(
+            *runner.audit.0.borrow(),
+            vec![
+                "permit alice permitted by the rule on line 1",
+                "deny alice denied by the rule on line 2",
+                "deny alice no matching rule",
+            ]
+        );
+
+        let mut runner = fake();
+        let config = Config::parse(
+            "permit bob
+",
+        )
+        .unwrap();
+        let err = runner.list(&config, &invocation).unwrap_err();
+        assert_eq!(err.to_string(), "no rules apply to alice");
+        assert_eq!(
+            *runner.audit.0.borrow(),
+            vec!["deny alice no rules apply to alice"]
+        );
+    }
+
+    #[test]
+    fn refusals_are_logged() {
+        let runner = fake();
+        let invocation = runner
+            .invocation(Uid::from_raw(1000), "root", command(&["id"]), Vec::new())
+            .unwrap();
+        let err = runner.refuse(&invocation, Error::Failed("no config".to_string()));
+        assert_eq!(err.to_string(), "no config");
+        assert_eq!(*runner.audit.0.borrow(), vec!["deny alice no config"]);
+    }
+}
@@ -0,0 +1,193 @@
+use std::fs::{self, File, Metadata, OpenOptions};
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+/// Open a file only root could have written.
+///
+/// The path must be absolute, every directory leading to it and the file itself must be owned by root
+/// and not writable by group or others, and no component may be a symlink.
+pub fn open_trusted(path: &Path) -> io::Result<File> {
+    if !path.is_absolute() {
+        return Err(insecure(path, "is not an absolute path"));
+    }
+    check_parents(path)?;
+
+    let file = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW | libc::O_CLOEXEC)
+        .open(path)
+        .map_err(|err| match err.raw_os_error() {
+            Some(libc::ELOOP) => insecure(path, "is a symlink"),
+            _ => context(path, err),
+        })?;
+
+    // check the file we actually opened rather than the path which could have changed since
+    let metadata = file.metadata().map_err(|err| context(path, err))?;
+    if !metadata.is_file() {
+        return Err(insecure(path, "is not a regular file"));
+    }
+    check_owner(path, &metadata)?;
+
+    Ok(file)
+}
+
+/// Make sure `dir` is a directory only root could have added files to.
+///
+/// The same rules as for [`open_trusted`] apply to it and every directory leading to it.
+pub fn check_trusted_dir(dir: &Path) -> io::Result<()> {
+    if !dir.is_absolute() {
+        return Err(insecure(dir, "is not an absolute path"));
+    }
+    check_parents(dir)?;
+
+    let metadata = fs::symlink_metadata(dir).map_err(|err| context(dir, err))?;
+    if metadata.file_type().is_symlink() {
+        return Err(insecure(dir, "is a symlink"));
+    }
+    if !metadata.is_dir() {
+        return Err(insecure(dir, "is not a directory"));
+    }
+    check_owner(dir, &metadata)
+}
+
+/// Resolve `path` to a program only root could have replaced.
+///
+/// Symlinks are followed since programs are often installed through them, but the file they end up
+/// at and every directory leading to it must be owned by root and not writable by group or others.
+pub fn trusted_program(path: &Path) -> io::Result<PathBuf> {
+    if !path.is_absolute() {
+        return Err(insecure(path, "is not an absolute path"));
+    }
+
+    let real = fs::canonicalize(path).map_err(|err| context(path, err))?;
+    check_parents(&real)?;
+
+    let metadata = fs::metadata(&real).map_err(|err| context(&real, err))?;
+    if !metadata.is_file() {
+        return Err(insecure(&real, "is not a regular file"));
+    }
+    if metadata.mode() & 0o111 == 0 {
+        return Err(insecure(&real, "is not executable"));
+    }
+    check_owner(&real, &metadata)?;
+
+    Ok(real)
+}
+
+/// Create `dir` if needed and make sure only root can look inside it.
+pub fn ensure_private_dir(dir: &Path) -> io::Result<()> {
+    match fs::DirBuilder::new().mode(0o700).create(dir) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+        Err(err) => return Err(context(dir, err)),
+    }
+
+    check_private_dir(dir)
+}
+
+/// Make sure `dir` is a real directory only root can look inside.
+pub fn check_private_dir(dir: &Path) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(dir).map_err(|err| context(dir, err))?;
+    if !metadata.is_dir() || metadata.uid() != 0 || metadata.mode() & 0o077 != 0 {
+        return Err(insecure(dir, "is not a directory only root can access"));
+    }
+
+    Ok(())
+}
+
+/// Walk the parents from the root down so the first problem reported is the outermost one.
+fn check_parents(path: &Path) -> io::Result<()> {
+    let mut parents: Vec<&Path> = path.ancestors().skip(1).collect();
+    parents.reverse();
+    for dir in parents {
+        let metadata = fs::symlink_metadata(dir).map_err(|err| context(dir, err))?;
+        if metadata.file_type().is_symlink() {
+            return Err(insecure(dir, "is a symlink"));
+        }
+        if !metadata.is_dir() {
+            return Err(insecure(dir, "is not a directory"));
+        }
+        check_owner(dir, &metadata)?;
+    }
+
+    Ok(())
+}
+
+fn check_owner(path: &Path, metadata: &Metadata) -> io::Result<()> {
+    if metadata.uid() != 0 {
+        return Err(insecure(path, "is not owned by root"));
+    }
+    if metadata.mode() & 0o022 != 0 {
+        return Err(insecure(path, "is writable by group or others"));
+    }
+
+    Ok(())
+}
+
+fn insecure(path: &Path, problem: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!("{} {}", path.display(), problem),
+    )
+}
+
+fn context(path: &Path, err: io::Error) -> io::Error {
+    io::Error::new(err.kind(), format!("{}: {}", path.display(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn problem(path: &str) -> String {
+        let err = open_trusted(Path::new(path)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        err.to_string()
+    }
+
+    #[test]
+    fn opens_files_only_root_can_write() {
+        assert!(open_trusted(Path::new("/etc/passwd")).is_ok());
+    }
+
+    #[test]
+    fn relative_paths_are_never_trusted() {
+        for err in [
+            open_trusted(Path::new("etc/execas.conf")).unwrap_err(),
+            check_trusted_dir(Path::new("etc/execas.d")).unwrap_err(),
+            trusted_program(Path::new("bin/askpass")).unwrap_err(),
+        ] {
+            assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+            assert!(err.to_string().ends_with("is not an absolute path"));
+        }
+    }
+
+    #[test]
+    fn refuses_anything_but_regular_files() {
+        assert_eq!(problem("/dev/null"), "/dev/null is not a regular file");
+    }
+
+    #[test]
+    fn programs_must_be_files() {
+        let err = trusted_program(Path::new("/")).unwrap_err();
+        assert_eq!(err.to_string(), "/ is not a regular file");
+    }
+
+    #[test]
+    fn refuses_symlinked_directories() {
+        // /proc/self links to the directory of the current process
+        assert_eq!(problem("/proc/self/stat"), "/proc/self is a symlink");
+    }
+
+    #[test]
+    fn refuses_files_in_writable_directories() {
+        // the temporary directory is writable by everyone, so it is refused before the file is
+        // even looked for
+        let file = env::temp_dir().join("execas.conf");
+        let err = open_trusted(&file).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    }
+}
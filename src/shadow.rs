@@ -0,0 +1,102 @@
+use std::ffi::{c_char, CStr, CString};
+use std::fs;
+use std::io;
+
+const SHADOW_PATH: &str = "/etc/shadow";
+
+// hash prefixes we are willing to verify, anything older (des, md5) is treated as a deny
+const SUPPORTED_PREFIXES: [&str; 6] = ["$y$", "$6$", "$5$", "$2a$", "$2b$", "$2y$"];
+
+#[link(name = "crypt")]
+extern "C" {
+    fn crypt(key: *const c_char, setting: *const c_char) -> *mut c_char;
+}
+
+/// Find the password hash for `user` in the shadow file.
+pub fn lookup(user: &str) -> io::Result<Option<String>> {
+    let shadow = fs::read_to_string(SHADOW_PATH)?;
+
+    // each line is name:hash:lastchange:min:max:warn:inactive:expire:reserved
+    let hash = shadow.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        match (fields.next(), fields.next()) {
+            (Some(name), Some(hash)) if name == user => Some(hash.to_string()),
+            _ => None,
+        }
+    });
+
+    Ok(hash)
+}
+
+/// Check `password` against a crypt(3) style `hash`.
+///
+/// Locked (`!` or `*` prefixed) and empty hashes never match.
+pub fn verify(password: &str, hash: &str) -> bool {
+    if hash.is_empty() || hash.starts_with('!') || hash.starts_with('*') {
+        return false;
+    }
+
+    if !SUPPORTED_PREFIXES
+        .iter()
+        .any(|prefix| hash.starts_with(prefix))
+    {
+        return false;
+    }
+
+    let (key, setting) = match (CString::new(password), CString::new(hash)) {
+        (Ok(key), Ok(setting)) => (key, setting),
+        _ => return false,
+    };
+
+    // crypt hands back a pointer into static storage, copy it out before anything else runs
+    let computed = unsafe {
+        let out = crypt(key.as_ptr(), setting.as_ptr());
+        if out.is_null() {
+            return false;
+        }
+        CStr::from_ptr(out).to_bytes().to_vec()
+    };
+
+    // failures are reported as a string starting with '*' which can never equal a valid hash
+    constant_time_eq(&computed, hash.as_bytes())
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // all made with `openssl passwd -<n> -salt saltsalt hunter2`
+    const SHA512: &str = "$6$saltsalt$8iYtNHxjWRl.NF6oNZ5tF.iKFlQREaXBLlSmZKP6dy9l5z3vsooWNW0/GZ6Nej73/TFug6pIPSqbJoCT6dfnj.";
+    const SHA256: &str = "$5$saltsalt$OIdfjX.u4Y3SJ4I2bX8w5BMf1VAUhHABNUirScDzZi3";
+    const MD5: &str = "$1$saltsalt$ZliGyAN3DciDHEkDboonh/";
+
+    #[test]
+    fn verifies_sha_crypt_hashes() {
+        assert!(verify("hunter2", SHA512));
+        assert!(!verify("hunter3", SHA512));
+        assert!(verify("hunter2", SHA256));
+        assert!(!verify("", SHA256));
+    }
+
+    #[test]
+    fn locked_and_empty_hashes_never_match() {
+        assert!(!verify("hunter2", &format!("!{}", SHA512)));
+        assert!(!verify("hunter2", "!"));
+        assert!(!verify("hunter2", "*"));
+        assert!(!verify("", ""));
+        assert!(!verify("hunter2", ""));
+    }
+
+    #[test]
+    fn unsupported_schemes_are_denied() {
+        assert!(!verify("hunter2", MD5));
+    }
+}
@@ -0,0 +1,171 @@
+use std::env;
+use std::ffi::OsString;
+use std::fmt::Write;
+use std::fs;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::process;
+
+use nix::sys::stat::{major, minor};
+
+use crate::persist;
+
+const SYSLOG_SOCKET: &str = "/dev/log";
+
+// facility and severities from syslog(3)
+const LOG_AUTHPRIV: u8 = 10 << 3;
+const LOG_NOTICE: u8 = 5;
+const LOG_INFO: u8 = 6;
+
+/// Who is trying to run what, as recorded in the audit trail.
+pub struct Attempt<'a> {
+    pub caller: &'a str,
+    pub target: &'a str,
+    pub command: &'a [OsString],
+}
+
+/// Where attempts are recorded.
+pub trait Audit {
+    /// Record that the command is about to be run.
+    fn permitted(&self, attempt: &Attempt, reason: &str);
+
+    /// Record that the command was refused.
+    fn denied(&self, attempt: &Attempt, reason: &str);
+}
+
+/// The local syslog daemon, under the `authpriv` facility.
+pub struct Syslog;
+
+impl Audit for Syslog {
+    fn permitted(&self, attempt: &Attempt, reason: &str) {
+        send(LOG_AUTHPRIV | LOG_INFO, &attempt.message("permit", reason));
+    }
+
+    fn denied(&self, attempt: &Attempt, reason: &str) {
+        send(LOG_AUTHPRIV | LOG_NOTICE, &attempt.message("deny", reason));
+    }
+}
+
+impl Attempt<'_> {
+    fn message(&self, decision: &str, reason: &str) -> String {
+        let tty = controlling_tty()
+            .map(|tty| tty.display().to_string())
+            .unwrap_or_else(|| "none".to_string());
+        let cwd = env::current_dir()
+            .map(|cwd| cwd.display().to_string())
+            .unwrap_or_else(|_| "unknown".to_string());
+        let command = self
+            .command
+            .iter()
+            .map(|arg| arg.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let mut message = String::new();
+        for (key, value) in [
+            ("user", self.caller),
+            ("target", self.target),
+            ("tty", &tty),
+            ("cwd", &cwd),
+            ("command", &command),
+            ("decision", decision),
+            ("reason", reason),
+        ] {
+            if !message.is_empty() {
+                message.push(' ');
+            }
+            let _ = write!(message, "{}={}", key, quote(value));
+        }
+
+        message
+    }
+}
+
+/// The controlling terminal, which unlike stdin is still known when input is piped to us.
+pub fn controlling_tty() -> Option<PathBuf> {
+    let device = device(persist::proc_stat_field("self", 7).ok()?)?;
+
+    // find the device node with that number, terminals are either pseudo terminals or consoles
+    for dir in ["/dev/pts", "/dev"] {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            // the entry metadata does not follow symlinks
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if metadata.file_type().is_char_device()
+                && (major(metadata.rdev()), minor(metadata.rdev())) == device
+            {
+                return Some(entry.path());
+            }
+        }
+    }
+
+    None
+}
+
+/// The major and minor number packed into the `tty_nr` field of /proc/<pid>/stat, `None` without
+/// a terminal.
+fn device(tty_nr: u64) -> Option<(u64, u64)> {
+    if tty_nr == 0 {
+        return None;
+    }
+    Some((
+        (tty_nr >> 8) & 0xfff,
+        (tty_nr & 0xff) | ((tty_nr >> 12) & 0xfff00),
+    ))
+}
+
+/// Quote a value so spaces and control characters can not forge fields or log lines.
+fn quote(value: &str) -> String {
+    if !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_graphic() && c != '"' && c != '\\')
+    {
+        return value.to_string();
+    }
+
+    format!("\"{}\"", value.escape_default())
+}
+
+/// Send a message to the local syslog daemon, there is nothing useful to do if that fails.
+fn send(priority: u8, message: &str) {
+    let socket = match UnixDatagram::unbound() {
+        Ok(socket) => socket,
+        Err(_) => return,
+    };
+
+    // the daemon fills in the timestamp and hostname when they are missing
+    let line = format!("<{}>execas[{}]: {}", priority, process::id(), message);
+    let _ = socket.send_to(line.as_bytes(), SYSLOG_SOCKET);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_terminal_numbers() {
+        assert_eq!(device(0), None);
+        // /dev/pts/3 and /dev/tty1
+        assert_eq!(device((136 << 8) | 3), Some((136, 3)));
+        assert_eq!(device((4 << 8) | 1), Some((4, 1)));
+        // minors above 255 are split around the major
+        assert_eq!(
+            device((136 << 8) | (300 & 0xff) | ((300 & !0xff) << 12)),
+            Some((136, 300))
+        );
+    }
+
+    #[test]
+    fn quotes_values_that_could_forge_fields() {
+        assert_eq!(quote("/usr/bin/id"), "/usr/bin/id");
+        assert_eq!(quote("a b"), "\"a b\"");
+        assert_eq!(quote("x\nuser=root"), "\"x\\nuser=root\"");
+        assert_eq!(quote(""), "\"\"");
+    }
+}
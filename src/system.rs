@@ -0,0 +1,353 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+#[cfg(feature = "pam")]
+use std::process;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use nix::sys::utsname::uname;
+#[cfg(feature = "pam")]
+use nix::sys::wait::WaitStatus;
+use nix::unistd::{chdir, execve, Uid, User};
+
+use crate::config::{Caller, Settings};
+use crate::error::Error;
+use crate::lockout::{Failures, Record, Verdict};
+use crate::persist::Timestamp;
+use crate::prompt::Prompter;
+use crate::run::{Authenticator, Clock, Edit, Execution, Executor, UserDb};
+#[cfg(not(feature = "pam"))]
+use crate::shadow;
+use crate::{edit, path, user};
+#[cfg(feature = "pam")]
+use crate::{pam, syslog};
+
+/// The passwd and group databases of this machine.
+pub struct SystemUsers;
+
+impl UserDb for SystemUsers {
+    fn by_uid(&self, uid: Uid) -> nix::Result<Option<User>> {
+        User::from_uid(uid)
+    }
+
+    fn by_name(&self, name: &str) -> nix::Result<Option<User>> {
+        User::from_name(name)
+    }
+
+    fn caller(&self, user: &User) -> nix::Result<Caller> {
+        user::caller(user)
+    }
+
+    fn is_login_shell(&self, shell: &OsStr) -> bool {
+        fs::read_to_string("/etc/shells")
+            .map(|shells| {
+                shells
+                    .lines()
+                    .map(str::trim)
+                    .any(|line| !line.starts_with('#') && OsStr::new(line) == shell)
+            })
+            .unwrap_or(false)
+    }
+
+    fn host_names(&self) -> Vec<String> {
+        let nodename = uname()
+            .map(|uts| uts.nodename().to_string_lossy().into_owned())
+            .unwrap_or_default();
+        host_names(
+            &nodename,
+            &fs::read_to_string("/etc/hostname").unwrap_or_default(),
+            &fs::read_to_string("/etc/hosts").unwrap_or_default(),
+        )
+    }
+}
+
+/// The nodename, the name in /etc/hostname and every alias /etc/hosts gives either of them, which
+/// is usually where the fully qualified name comes from.
+fn host_names(nodename: &str, hostname: &str, hosts: &str) -> Vec<String> {
+    let mut names = vec![nodename.to_string()];
+    if let Some(name) = hostname
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+    {
+        names.push(name.to_string());
+    }
+
+    for line in hosts.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        // the first field is the address
+        let aliases: Vec<&str> = line.split_whitespace().skip(1).collect();
+        if aliases
+            .iter()
+            .any(|alias| names.iter().any(|name| name.eq_ignore_ascii_case(alias)))
+        {
+            names.extend(aliases.iter().map(|alias| alias.to_string()));
+        }
+    }
+
+    let mut unique: Vec<String> = Vec::new();
+    for name in names {
+        if !name.is_empty() && !unique.contains(&name) {
+            unique.push(name);
+        }
+    }
+    unique
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO)
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// Proof of a successful authentication, with PAM the transaction has to stay open for the session.
+#[cfg(feature = "pam")]
+pub type Authenticated = pam::Pam;
+#[cfg(not(feature = "pam"))]
+pub struct Authenticated;
+
+/// Authenticates against PAM or /etc/shadow, throttling and locking out callers who keep getting
+/// their password wrong.
+pub struct SystemAuthenticator<C> {
+    pub clock: C,
+    pub settings: Settings,
+    pub prompter: Prompter,
+}
+
+impl<C: Clock> Authenticator for SystemAuthenticator<C> {
+    type Session = Authenticated;
+
+    fn persisted(&self, user: &User, timeout: Duration) -> bool {
+        Timestamp::current(user.uid).is_ok_and(|timestamp| timestamp.is_valid(timeout))
+    }
+
+    fn remember(&self, user: &User) -> io::Result<()> {
+        Timestamp::current(user.uid)?.update()
+    }
+
+    fn authenticate(&mut self, user: &User, prompt: bool) -> Result<Authenticated, Error> {
+        if !prompt {
+            return authenticate(user, false, &self.prompter).map_err(Failure::into_error);
+        }
+
+        // the record stays locked until the attempt is over so parallel prompts can not skip the
+        // delay
+        let mut failures = Failures::lock(user.uid).map_err(|err| {
+            Error::Failed(format!(
+                "failed to read the failed authentications: {}",
+                err
+            ))
+        })?;
+
+        match failures.record.verdict(&self.settings, self.clock.now()) {
+            Verdict::Locked(left) => {
+                return Err(Error::Authentication(format!(
+                    "Too many failed authentications, try again in {} seconds",
+                    left.as_secs().max(1)
+                )))
+            }
+            Verdict::Wait(delay) => self.clock.sleep(delay),
+        }
+
+        // count the attempt as failed up front, being killed while it is checked must not get the
+        // caller a free guess
+        let before = failures.record;
+        failures.record.failed(&self.settings, self.clock.now());
+        failures
+            .save()
+            .map_err(|err| Error::Failed(format!("failed to record the attempt: {}", err)))?;
+
+        let result = authenticate(user, true, &self.prompter);
+        match &result {
+            Ok(_) => failures.record = Record::default(),
+            Err(Failure::Rejected(_)) => failures.record.last = self.clock.now(),
+            // only a wrong password counts, not failing to ask for or check one
+            Err(Failure::Unverified(_)) => failures.record = before,
+        }
+        if let Err(err) = failures.save() {
+            eprintln!("execas: failed to record the attempt: {}", err);
+        }
+
+        result.map_err(Failure::into_error)
+    }
+}
+
+/// Why an authentication did not succeed.
+enum Failure {
+    /// The credential was checked and turned down.
+    Rejected(Error),
+    /// No credential could be asked for or checked.
+    Unverified(Error),
+}
+
+impl Failure {
+    fn into_error(self) -> Error {
+        match self {
+            Failure::Rejected(err) | Failure::Unverified(err) => err,
+        }
+    }
+}
+
+/// Make sure the caller is who they say they are, only asking for a password when `prompt` is set.
+#[cfg(feature = "pam")]
+fn authenticate(
+    real_user: &User,
+    prompt: bool,
+    prompter: &Prompter,
+) -> Result<Authenticated, Failure> {
+    // the terminal the caller sits at, which stdin need not be
+    let tty = syslog::controlling_tty();
+    let tty = tty.as_ref().and_then(|tty| tty.to_str());
+
+    pam::Pam::start(&real_user.name, tty, prompter.clone())
+        .and_then(|mut pam| pam.authenticate(prompt).map(|_| pam))
+        .map_err(|err| {
+            let rejected = err.rejected();
+            let err = Error::Authentication(format!("Authentication failed: {}", err));
+            if rejected {
+                Failure::Rejected(err)
+            } else {
+                Failure::Unverified(err)
+            }
+        })
+}
+
+/// Make sure the caller is who they say they are, only asking for a password when `prompt` is set.
+#[cfg(not(feature = "pam"))]
+fn authenticate(
+    real_user: &User,
+    prompt: bool,
+    prompter: &Prompter,
+) -> Result<Authenticated, Failure> {
+    if !prompt {
+        return Ok(Authenticated);
+    }
+
+    let given_password = prompter.read_password("password: ").map_err(|err| {
+        Failure::Unverified(Error::Authentication(format!(
+            "failed to read password: {}",
+            err
+        )))
+    })?;
+
+    // compare the given password against the users hash in the shadow file
+    let hash = shadow::lookup(&real_user.name)
+        .map_err(|err| {
+            Failure::Unverified(Error::Failed(format!(
+                "failed to read the shadow file: {}",
+                err
+            )))
+        })?
+        .unwrap_or_default();
+
+    if !shadow::verify(&given_password, &hash) {
+        return Err(Failure::Rejected(Error::Authentication(
+            "Authentication failed".to_string(),
+        )));
+    }
+
+    Ok(Authenticated)
+}
+
+/// Runs commands by becoming the target and replacing this process, edits are done by this process.
+pub struct SystemExecutor;
+
+impl Executor<Authenticated> for SystemExecutor {
+    fn resolve(&self, command: &OsStr, search_path: &OsStr) -> io::Result<PathBuf> {
+        path::resolve(command, search_path)
+    }
+
+    #[cfg(feature = "pam")]
+    fn execute(
+        &mut self,
+        session: Authenticated,
+        target: &User,
+        execution: &Execution,
+    ) -> Result<i32, Error> {
+        // the session is closed once the command exits so we have to wait for it
+        let mut pam = session;
+        let status = pam.run(&target.name, || {
+            let err = exec_as(target, execution);
+            eprintln!("execas: {}", err);
+            process::exit(err.exit_code());
+        });
+        drop(pam);
+
+        match status {
+            Ok(WaitStatus::Exited(_, code)) => Ok(code),
+            Ok(WaitStatus::Signaled(_, signal, _)) => Ok(128 + signal as i32),
+            Ok(_) => Ok(1),
+            Err(err) => Err(Error::Failed(err.to_string())),
+        }
+    }
+
+    #[cfg(not(feature = "pam"))]
+    fn execute(
+        &mut self,
+        _session: Authenticated,
+        target: &User,
+        execution: &Execution,
+    ) -> Result<i32, Error> {
+        Err(exec_as(target, execution))
+    }
+
+    fn edit(
+        &mut self,
+        _session: Authenticated,
+        user: &User,
+        target: &User,
+        edit: &Edit,
+    ) -> Result<i32, Error> {
+        edit::edit(user, target, edit)
+    }
+}
+
+/// Become `target` and exec the command, only returning if that fails.
+fn exec_as(target: &User, execution: &Execution) -> Error {
+    // change both the real and effective ids so the command sees a consistent identity
+    if let Err(err) = user::become_user(target) {
+        return Error::Failed(format!("failed to switch to user {}: {}", target.name, err));
+    }
+
+    if let Some(cwd) = &execution.cwd {
+        if let Err(err) = chdir(cwd.as_path()) {
+            return Error::Failed(format!(
+                "failed to change directory to {}: {}",
+                cwd.display(),
+                err
+            ));
+        }
+    }
+
+    // exec the command the user is trying to run, the kind of error decides the exit status
+    let Err(err) = execve(&execution.path, &execution.args, &execution.env);
+    Error::Command(io::Error::new(
+        io::Error::from(err).kind(),
+        format!("{}: {}", execution.path.to_string_lossy(), err),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_names_include_aliases_from_hosts() {
+        let hosts = "127.0.0.1 localhost\n10.0.0.5 db1.example.com db1 # primary\n10.0.0.6 db2\n";
+        assert_eq!(
+            host_names("db1", "# set by the installer\ndb1\n", hosts),
+            vec!["db1", "db1.example.com"]
+        );
+        assert_eq!(host_names("box", "", "# nothing\n"), vec!["box"]);
+    }
+}
@@ -0,0 +1,85 @@
+use std::ffi::CString;
+
+use nix::errno::Errno;
+use nix::unistd::{
+    getegid, getgid, getgrouplist, getgroups, getresgid, getresuid, getuid, initgroups, setegid,
+    seteuid, setgroups, setresgid, setresuid, setuid, Group, Uid, User,
+};
+
+use crate::config::Caller;
+
+/// Permanently switch the process to `target`.
+///
+/// The group id and supplementary groups are set first since changing them requires root, then the
+/// real, effective and saved user ids. Afterwards the new ids are read back and, for a non root
+/// target, we make sure root can not be regained.
+pub fn become_user(target: &User) -> nix::Result<()> {
+    let name = CString::new(target.name.as_bytes()).map_err(|_| Errno::EINVAL)?;
+
+    setresgid(target.gid, target.gid, target.gid)?;
+    initgroups(&name, target.gid)?;
+    setresuid(target.uid, target.uid, target.uid)?;
+
+    // verify the change actually stuck before we hand the process over
+    let uids = getresuid()?;
+    let gids = getresgid()?;
+    if [uids.real, uids.effective, uids.saved] != [target.uid; 3]
+        || [gids.real, gids.effective, gids.saved] != [target.gid; 3]
+    {
+        return Err(Errno::EPERM);
+    }
+
+    if !target.uid.is_root() && setuid(Uid::from_raw(0)).is_ok() {
+        return Err(Errno::EPERM);
+    }
+
+    Ok(())
+}
+
+/// Give up the privileges granted by the setuid bit and go back to being the invoking user.
+pub fn drop_privileges() -> nix::Result<()> {
+    let (uid, gid) = (getuid(), getgid());
+    setresgid(gid, gid, gid)?;
+    setresuid(uid, uid, uid)
+}
+
+/// Run `f` with the effective ids and groups of `user` and switch back to root afterwards.
+///
+/// Only the effective ids change so root can be regained, which also means `f` must never run
+/// anything the user controls.
+pub fn as_user<T>(user: &User, f: impl FnOnce() -> T) -> nix::Result<T> {
+    let name = CString::new(user.name.as_bytes()).map_err(|_| Errno::EINVAL)?;
+    let (groups, gid) = (getgroups()?, getegid());
+
+    setgroups(&getgrouplist(&name, user.gid)?)?;
+    setegid(user.gid)?;
+    seteuid(user.uid)?;
+
+    let result = f();
+
+    seteuid(Uid::from_raw(0))?;
+    setegid(gid)?;
+    setgroups(&groups)?;
+
+    Ok(result)
+}
+
+/// Describe `user` for rule matching.
+///
+/// The groups come from the group database rather than the process, whose supplementary groups
+/// could be out of date or dropped by whoever started us.
+pub fn caller(user: &User) -> nix::Result<Caller> {
+    let name = CString::new(user.name.as_bytes()).map_err(|_| Errno::EINVAL)?;
+
+    let mut groups = Vec::new();
+    for gid in getgrouplist(&name, user.gid)? {
+        if let Some(group) = Group::from_gid(gid)? {
+            groups.push(group.name);
+        }
+    }
+
+    Ok(Caller {
+        name: user.name.clone(),
+        groups,
+    })
+}